@@ -2,20 +2,341 @@ use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_kira_audio::AudioSource;
 
-use std::collections::HashMap;
+use crate::generator::GeneratorSettings;
+use crate::save::Split;
 
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum TileType {
     Wall,
     Ladder,
+    // A wall that gives way a few turns after it's been stood on
+    Crumbling,
+    // A wall that's too slippery to stop on
+    Slick,
+    // What's left of shattered ice, until it melts away
+    Debris,
+    // Where a push block is sitting, so it can be stood on
+    PushBlock,
+    // A shut gate or locked door
+    Gate,
+}
+
+impl TileType {
+    pub fn is_solid(&self) -> bool {
+        match self {
+            TileType::Wall
+            | TileType::Crumbling
+            | TileType::Slick
+            | TileType::Debris
+            | TileType::PushBlock
+            | TileType::Gate => true,
+            TileType::Ladder => false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TileMap(pub HashMap<IVec2, TileType>);
+
+// How the levels of the LDtk project are put together, picked on the main menu
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum WorldLayout {
+    // One level at a time, reaching the goal swaps to the next one
+    #[default]
+    Separate,
+    // Levels sit where they are in the LDtk world, and walking off the edge
+    // of one goes into its neighbour
+    Connected,
+}
+
+impl WorldLayout {
+    pub fn toggled(&self) -> Self {
+        match self {
+            WorldLayout::Separate => WorldLayout::Connected,
+            WorldLayout::Connected => WorldLayout::Separate,
+        }
+    }
+
+    pub fn ldtk_settings(&self) -> LdtkSettings {
+        let connected = *self == WorldLayout::Connected;
+        LdtkSettings {
+            use_level_world_translations: connected,
+            load_level_neighbors: connected,
+            ..Default::default()
+        }
+    }
+}
+
+// Where a spawned level sits in the world and how big it is, in tiles
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LevelDimensions {
+    pub index: usize,
+    pub origin: IVec2,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl LevelDimensions {
+    pub fn contains(&self, grid_pos: IVec2) -> bool {
+        let local_pos = grid_pos - self.origin;
+        (0..self.width).contains(&local_pos.x) && (0..self.height).contains(&local_pos.y)
+    }
+}
+
+// Per-level tweaks read from the level's own LDtk fields
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LevelRules {
+    // Heal regen_amount every regen_every turns, 0 for no regeneration
+    pub regen_every: u32,
+    pub regen_amount: i32,
+    // Falls of up to fall_safe_height tiles don't hurt, each tile past that does
+    // fall_damage_per_tile. Falling fall_lethal_height tiles kills, 0 for never.
+    pub fall_safe_height: u32,
+    pub fall_damage_per_tile: i32,
+    pub fall_lethal_height: u32,
+    // Whether ladders catch a falling player, or only the ground does
+    pub grab_ladders: bool,
+    // Taking more than move_limit moves fails the level, and finishing within par
+    // earns all three stars. 0 for neither.
+    pub move_limit: u32,
+    pub par: u32,
+}
+
+impl Default for LevelRules {
+    fn default() -> Self {
+        Self {
+            regen_every: 0,
+            regen_amount: 0,
+            fall_safe_height: 3,
+            fall_damage_per_tile: 10,
+            fall_lethal_height: 0,
+            grab_ladders: true,
+            move_limit: 0,
+            par: 0,
+        }
+    }
+}
+
+impl LevelRules {
+    pub fn from_level(level: &ldtk::Level) -> Self {
+        let fields = &level.field_instances;
+        let default = LevelRules::default();
+        Self {
+            regen_every: uint_field(fields, "RegenEvery").unwrap_or(default.regen_every),
+            regen_amount: int_field(fields, "RegenAmount").unwrap_or(default.regen_amount),
+            fall_safe_height: uint_field(fields, "FallSafeHeight")
+                .unwrap_or(default.fall_safe_height),
+            fall_damage_per_tile: int_field(fields, "FallDamagePerTile")
+                .unwrap_or(default.fall_damage_per_tile),
+            fall_lethal_height: uint_field(fields, "FallLethalHeight")
+                .unwrap_or(default.fall_lethal_height),
+            grab_ladders: bool_field(fields, "GrabLadders").unwrap_or(default.grab_ladders),
+            move_limit: uint_field(fields, "MoveLimit").unwrap_or(default.move_limit),
+            par: uint_field(fields, "Par").unwrap_or(default.par),
+        }
+    }
+
+    pub fn fall_damage(&self, height: u32) -> Option<i32> {
+        if self.fall_lethal_height > 0 && height >= self.fall_lethal_height {
+            return None; // no surviving that
+        }
+        Some(height.saturating_sub(self.fall_safe_height) as i32 * self.fall_damage_per_tile)
+    }
+
+    pub fn is_out_of_moves(&self, moves: u32) -> bool {
+        self.move_limit > 0 && moves > self.move_limit
+    }
+
+    // Three stars for making par, two for needing up to half as many moves again,
+    // one for just finishing
+    pub fn stars(&self, moves: u32) -> Option<u32> {
+        if self.par == 0 {
+            None
+        } else if moves <= self.par {
+            Some(3)
+        } else if moves <= self.par + self.par / 2 {
+            Some(2)
+        } else {
+            Some(1)
+        }
+    }
+}
+
+// Every level currently spawned, by LDtk uid. Grid positions are in tiles from the
+// bottom-left of the world, which is also the bottom-left of the level when
+// levels are played one at a time.
+pub struct LoadedLevels {
+    pub grid_size: i32,
+    pub levels: HashMap<i32, LevelDimensions>,
+    pub rules: HashMap<i32, LevelRules>,
+    pub names: HashMap<i32, String>,
+}
+
+impl Default for LoadedLevels {
+    fn default() -> Self {
+        Self {
+            grid_size: 64,
+            levels: HashMap::new(),
+            rules: HashMap::new(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+impl LoadedLevels {
+    pub fn insert(&mut self, level: &ldtk::Level, index: usize, translation: Vec2) {
+        if let Some(grid_size) = level
+            .layer_instances
+            .iter()
+            .flatten()
+            .find(|l| l.identifier == "Terrain")
+            .map(|l| l.grid_size)
+        {
+            self.grid_size = grid_size;
+        }
+
+        self.levels.insert(
+            level.uid,
+            LevelDimensions {
+                index,
+                origin: (translation / self.grid_size as f32).round().as_ivec2(),
+                width: level.px_wid / self.grid_size,
+                height: level.px_hei / self.grid_size,
+            },
+        );
+        self.rules.insert(level.uid, LevelRules::from_level(level));
+
+        // Levels without a "Name" field go by their LDtk identifier
+        let name = string_field(&level.field_instances, "Name")
+            .map(str::to_string)
+            .unwrap_or_else(|| level.identifier.replace('_', " "));
+        self.names.insert(level.uid, name);
+    }
+
+    pub fn remove(&mut self, uid: i32) -> Option<LevelDimensions> {
+        self.rules.remove(&uid);
+        self.names.remove(&uid);
+        self.levels.remove(&uid)
+    }
+
+    pub fn rules_at(&self, grid_pos: IVec2) -> LevelRules {
+        self.uid_at(grid_pos)
+            .and_then(|uid| self.rules.get(&uid))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn name_at(&self, grid_pos: IVec2) -> Option<&str> {
+        self.uid_at(grid_pos)
+            .and_then(|uid| self.names.get(&uid))
+            .map(|name| name.as_str())
+    }
+
+    pub fn origin_of(&self, uid: i32) -> IVec2 {
+        self.levels
+            .get(&uid)
+            .map(|l| l.origin)
+            .unwrap_or(IVec2::ZERO)
+    }
+
+    pub fn uid_at(&self, grid_pos: IVec2) -> Option<i32> {
+        self.levels
+            .iter()
+            .find(|(_, l)| l.contains(grid_pos))
+            .map(|(uid, _)| *uid)
+    }
+
+    pub fn level_at(&self, grid_pos: IVec2) -> Option<&LevelDimensions> {
+        self.levels.values().find(|l| l.contains(grid_pos))
+    }
+
+    pub fn contains(&self, grid_pos: IVec2) -> bool {
+        self.level_at(grid_pos).is_some()
+    }
+
+    // Center of the tile, relative to the world
+    pub fn grid_to_translation(&self, grid_pos: IVec2) -> Vec2 {
+        let size = self.grid_size as f32;
+        let half = size / 2.;
+        Vec2::new(
+            grid_pos.x as f32 * size + half,
+            grid_pos.y as f32 * size + half,
+        )
+    }
 }
 
+// What an entity is, as far as the rules looking up a tile are concerned
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+pub enum Role {
+    Player,
+    Obstacle,
+    StaticIce,
+    FallingIce,
+    Goal,
+    Enemy,
+    PushBlock,
+    Key,
+    Lever,
+    Door,
+    Gem,
+    Checkpoint,
+    HealthPickup,
+    RestSpot,
+}
+
+// Which entities are on which tile, kept up to date as they spawn, move and despawn
 #[derive(Default)]
-pub struct TileMap(pub HashMap<(i32, i32), TileType>);
+pub struct Occupancy {
+    tiles: HashMap<IVec2, Vec<(Entity, Role)>>,
+    positions: HashMap<Entity, IVec2>,
+}
+
+impl Occupancy {
+    pub fn insert(&mut self, entity: Entity, grid_pos: IVec2, role: Role) {
+        self.remove(entity);
+        self.tiles.entry(grid_pos).or_default().push((entity, role));
+        self.positions.insert(entity, grid_pos);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(grid_pos) = self.positions.remove(&entity) {
+            if let Some(occupants) = self.tiles.get_mut(&grid_pos) {
+                occupants.retain(|(e, _)| *e != entity);
+                if occupants.is_empty() {
+                    self.tiles.remove(&grid_pos);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+        self.positions.clear();
+    }
+
+    pub fn at(&self, grid_pos: IVec2) -> impl Iterator<Item = (Entity, Role)> + '_ {
+        self.tiles.get(&grid_pos).into_iter().flatten().copied()
+    }
+
+    pub fn with_role_at(&self, grid_pos: IVec2, role: Role) -> impl Iterator<Item = Entity> + '_ {
+        self.at(grid_pos)
+            .filter(move |(_, r)| *r == role)
+            .map(|(e, _)| e)
+    }
+
+    pub fn has_role_at(&self, grid_pos: IVec2, role: Role) -> bool {
+        self.with_role_at(grid_pos, role).next().is_some()
+    }
+}
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum AppState {
     MainMenu,
     InGame,
+    // Shown over the paused game once a level's goal is reached
+    LevelResults,
     Credits,
 }
 
@@ -44,9 +365,18 @@ pub struct GameSounds {
     pub player_hit_sfxs: Vec<Handle<AudioSource>>,
     pub player_attack_sfx: Handle<AudioSource>,
     pub falling_ice_sfx: Handle<AudioSource>,
+    pub ice_shatter_sfx: Handle<AudioSource>,
+    pub gem_sfx: Handle<AudioSource>,
     pub goal_sfx: Handle<AudioSource>,
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PlayerAction {
+    Move(IVec2),
+    Attack(IVec2),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Advantage {
     Speed,
     Strength,
@@ -54,27 +384,115 @@ pub enum Advantage {
 }
 
 pub struct GameState {
-    pub player_previous_pos: Vec3,
+    pub player_previous_pos: IVec2,
+    // Whether the player stepped anywhere since the world last updated, as only walking
+    // into something blocking bounces them back
+    pub player_has_moved: bool,
+    pub player_buffered_actions: VecDeque<PlayerAction>,
     pub world_should_update: bool,
     pub player_num_actions_taken: u32,
     pub player_is_falling: bool,
     pub player_advantage: Option<Advantage>,
     pub level_index: usize,
+    pub player_keys: u32,
+    pub turns_since_regen: u32,
+    // Tiles fallen since the player last stood on something
+    pub player_fall_height: u32,
+    // How the level the player is in has gone since they started it. In a connected
+    // world that's the highest level they've climbed to, and stepping back down into
+    // one below keeps adding to it rather than starting the lower one over.
+    pub level_moves: u32,
+    pub level_damage_taken: i32,
+    pub level_deaths: u32,
+    pub level_time: f32,
+    // Playing a generated level instead of the hand-made ones
+    pub generated_level: Option<GeneratedLevel>,
 }
 
 impl Default for GameState {
     fn default() -> Self {
         Self {
-            player_previous_pos: Vec3::ZERO,
+            player_previous_pos: IVec2::ZERO,
+            player_has_moved: false,
+            player_buffered_actions: VecDeque::new(),
             world_should_update: false,
             player_num_actions_taken: 0,
             player_is_falling: false,
             player_advantage: None,
             level_index: 0,
+            player_keys: 0,
+            turns_since_regen: 0,
+            player_fall_height: 0,
+            level_moves: 0,
+            level_damage_taken: 0,
+            level_deaths: 0,
+            level_time: 0.0,
+            generated_level: None,
         }
     }
 }
 
+impl GameState {
+    // Only for reaching a level for the first time, see follow_player_into_neighbour_level
+    pub fn start_level(&mut self, level_index: usize) {
+        self.level_index = level_index;
+        self.level_moves = 0;
+        self.level_damage_taken = 0;
+        self.level_deaths = 0;
+        self.level_time = 0.0;
+    }
+}
+
+// How long it takes for a sprite to visually travel a single tile, in seconds
+pub struct TweenSettings {
+    pub step_duration: f32,
+}
+
+impl Default for TweenSettings {
+    fn default() -> Self {
+        Self {
+            step_duration: 0.08,
+        }
+    }
+}
+
+// Where the turn logic thinks an entity is, in tiles from the bottom-left of the world.
+// LDtk spawns it relative to the entity's level until it's placed in the world.
+// Transforms are derived from it, never the other way around.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Component)]
+pub struct GridPos(pub IVec2);
+
+impl GridPos {
+    pub fn from_entity_info(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+    ) -> Self {
+        Self(GridCoords::from_entity_info(entity_instance, layer_instance).into())
+    }
+}
+
+// Tiles the rendered Transform still has to go through, one at a time,
+// to catch up with the entity's GridPos
+#[derive(Clone, PartialEq, Debug, Default, Component)]
+pub struct Tween {
+    pub from: IVec2,
+    pub waypoints: VecDeque<IVec2>,
+    pub elapsed: f32,
+}
+
+impl Tween {
+    pub fn starting_at(grid_pos: GridPos) -> Self {
+        Self {
+            from: grid_pos.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.waypoints.is_empty()
+    }
+}
+
 #[derive(Clone, Component)]
 pub struct Speed(pub u8);
 
@@ -84,6 +502,17 @@ pub struct Damage(pub i32);
 #[derive(Clone, Component)]
 pub struct Health(pub i32);
 
+// Healing never goes past this
+#[derive(Clone, Component)]
+pub struct MaxHealth(pub i32);
+
+// How many tiles the player's attack reaches, and how hard it hits
+#[derive(Clone, Component)]
+pub struct Attack {
+    pub reach: i32,
+    pub damage: i32,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Player;
 
@@ -95,17 +524,27 @@ pub struct PlayerBundle {
     pub speed: Speed,
     pub damage: Damage,
     pub health: Health,
+    pub max_health: MaxHealth,
+    pub attack: Attack,
+    pub status_effects: StatusEffects,
+    pub grid_pos: GridPos,
+    pub tween: Tween,
+    pub role: Role,
+    // Outlives its level, so the player can walk into neighbouring ones
+    pub worldly: Worldly,
 }
 
 impl LdtkEntity for PlayerBundle {
     fn bundle_entity(
-        _: &EntityInstance,
-        _: &LayerInstance,
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
         _: Option<&Handle<Image>>,
         _: Option<&TilesetDefinition>,
         asset_server: &AssetServer,
         _: &mut Assets<TextureAtlas>,
     ) -> Self {
+        let grid_pos = GridPos::from_entity_info(entity_instance, layer_instance);
+
         Self {
             sprite_bundle: SpriteBundle {
                 texture: asset_server.load("Player.png"),
@@ -115,6 +554,16 @@ impl LdtkEntity for PlayerBundle {
             speed: Speed(1),
             damage: Damage(0),
             health: Health(100),
+            max_health: MaxHealth(100),
+            attack: Attack {
+                reach: 1,
+                damage: 50,
+            },
+            status_effects: StatusEffects::default(),
+            grid_pos,
+            tween: Tween::starting_at(grid_pos),
+            role: Role::Player,
+            worldly: Worldly::from_entity_info(entity_instance, layer_instance),
         }
     }
 }
@@ -125,6 +574,10 @@ pub struct Obstacle;
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Blocking(pub bool);
 
+// Which way a hit sends the player, along the way the obstacle is pointing
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Knockback(pub IVec2);
+
 #[derive(Clone, Bundle)]
 pub struct ObstacleSpikeBundle {
     #[bundle]
@@ -133,49 +586,25 @@ pub struct ObstacleSpikeBundle {
     pub damage: Damage,
     pub health: Health,
     pub blocking: Blocking,
+    pub knockback: Knockback,
+    pub inflicts_status: InflictsStatus,
+    pub grid_pos: GridPos,
+    pub role: Role,
 }
 
 impl LdtkEntity for ObstacleSpikeBundle {
     fn bundle_entity(
         entity_instance: &EntityInstance,
-        _: &LayerInstance,
+        layer_instance: &LayerInstance,
         _: Option<&Handle<Image>>,
         _: Option<&TilesetDefinition>,
         asset_server: &AssetServer,
         _: &mut Assets<TextureAtlas>,
     ) -> Self {
-        let mut blocking = Blocking(false);
-        if let Some(blocking_field) = entity_instance
-            .field_instances
-            .iter()
-            .find(|f| f.identifier == "IsBlocking".to_string())
-        {
-            if let FieldValue::Bool(blocking_value) = blocking_field.value {
-                blocking = Blocking(blocking_value);
-            }
-        }
-
-        let mut damage = Damage(0);
-        if let Some(damage_field) = entity_instance
-            .field_instances
-            .iter()
-            .find(|f| f.identifier == "Damage".to_string())
-        {
-            if let FieldValue::Int(Some(damage_value)) = damage_field.value {
-                damage = Damage(damage_value);
-            }
-        }
-
-        let mut health = Health(100);
-        if let Some(health_field) = entity_instance
-            .field_instances
-            .iter()
-            .find(|f| f.identifier == "Health".to_string())
-        {
-            if let FieldValue::Int(Some(health_value)) = health_field.value {
-                health = Health(health_value);
-            }
-        }
+        let fields = &entity_instance.field_instances;
+        let blocking = Blocking(bool_field(fields, "IsBlocking").unwrap_or(false));
+        let damage = Damage(int_field(fields, "Damage").unwrap_or(0));
+        let health = Health(int_field(fields, "Health").unwrap_or(100));
 
         Self {
             sprite_bundle: SpriteBundle {
@@ -186,6 +615,10 @@ impl LdtkEntity for ObstacleSpikeBundle {
             damage,
             health,
             blocking,
+            knockback: Knockback(direction_field(fields, "Facing").unwrap_or_default()),
+            inflicts_status: InflictsStatus::from_fields(entity_instance),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::Obstacle,
         }
     }
 }
@@ -198,49 +631,24 @@ pub struct ObstacleBlockBundle {
     pub damage: Damage,
     pub health: Health,
     pub blocking: Blocking,
+    pub inflicts_status: InflictsStatus,
+    pub grid_pos: GridPos,
+    pub role: Role,
 }
 
 impl LdtkEntity for ObstacleBlockBundle {
     fn bundle_entity(
         entity_instance: &EntityInstance,
-        _: &LayerInstance,
+        layer_instance: &LayerInstance,
         _: Option<&Handle<Image>>,
         _: Option<&TilesetDefinition>,
         asset_server: &AssetServer,
         _: &mut Assets<TextureAtlas>,
     ) -> Self {
-        let mut blocking = Blocking(false);
-        if let Some(blocking_field) = entity_instance
-            .field_instances
-            .iter()
-            .find(|f| f.identifier == "IsBlocking".to_string())
-        {
-            if let FieldValue::Bool(blocking_value) = blocking_field.value {
-                blocking = Blocking(blocking_value);
-            }
-        }
-
-        let mut damage = Damage(0);
-        if let Some(damage_field) = entity_instance
-            .field_instances
-            .iter()
-            .find(|f| f.identifier == "Damage".to_string())
-        {
-            if let FieldValue::Int(Some(damage_value)) = damage_field.value {
-                damage = Damage(damage_value);
-            }
-        }
-
-        let mut health = Health(100);
-        if let Some(health_field) = entity_instance
-            .field_instances
-            .iter()
-            .find(|f| f.identifier == "Health".to_string())
-        {
-            if let FieldValue::Int(Some(health_value)) = health_field.value {
-                health = Health(health_value);
-            }
-        }
+        let fields = &entity_instance.field_instances;
+        let blocking = Blocking(bool_field(fields, "IsBlocking").unwrap_or(false));
+        let damage = Damage(int_field(fields, "Damage").unwrap_or(0));
+        let health = Health(int_field(fields, "Health").unwrap_or(100));
 
         Self {
             sprite_bundle: SpriteBundle {
@@ -251,6 +659,51 @@ impl LdtkEntity for ObstacleBlockBundle {
             damage,
             health,
             blocking,
+            inflicts_status: InflictsStatus::from_fields(entity_instance),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::Obstacle,
+        }
+    }
+}
+
+// A block the player can push along by walking into it, it falls when there's nothing under it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct PushBlock;
+
+#[derive(Clone, Bundle)]
+pub struct PushBlockBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub push_block: PushBlock,
+    pub grid_pos: GridPos,
+    pub tween: Tween,
+    pub role: Role,
+}
+
+impl LdtkEntity for PushBlockBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        asset_server: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        let grid_pos = GridPos::from_entity_info(entity_instance, layer_instance);
+
+        Self {
+            sprite_bundle: SpriteBundle {
+                texture: asset_server.load("ObstacleBlock.png"),
+                sprite: Sprite {
+                    color: Color::rgb_u8(214, 190, 150),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            push_block: PushBlock::default(),
+            grid_pos,
+            tween: Tween::starting_at(grid_pos),
+            role: Role::PushBlock,
         }
     }
 }
@@ -263,12 +716,14 @@ pub struct GoalBundle {
     #[bundle]
     pub sprite_bundle: SpriteBundle,
     pub goal: Goal,
+    pub grid_pos: GridPos,
+    pub role: Role,
 }
 
 impl LdtkEntity for GoalBundle {
     fn bundle_entity(
-        _: &EntityInstance,
-        _: &LayerInstance,
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
         _: Option<&Handle<Image>>,
         _: Option<&TilesetDefinition>,
         asset_server: &AssetServer,
@@ -280,6 +735,102 @@ impl LdtkEntity for GoalBundle {
                 ..Default::default()
             },
             goal: Goal::default(),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::Goal,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Enemy;
+
+// Tiles an enemy walks between, in order, looping back to the first one
+#[derive(Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Patrol {
+    pub waypoints: Vec<IVec2>,
+    pub next: usize,
+}
+
+impl Patrol {
+    // The waypoint to head to, skipping the one the enemy is already standing on
+    pub fn target_from(&self, grid_pos: IVec2) -> Option<(usize, IVec2)> {
+        let index = self.next % self.waypoints.len().max(1);
+        let target = *self.waypoints.get(index)?;
+        if target == grid_pos {
+            let index = (index + 1) % self.waypoints.len();
+            Some((index, self.waypoints[index]))
+        } else {
+            Some((index, target))
+        }
+    }
+}
+
+// Highlights the tile an enemy is about to move to
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+pub struct EnemyTelegraph(pub Entity);
+
+#[derive(Clone, Bundle)]
+pub struct YetiBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub enemy: Enemy,
+    pub damage: Damage,
+    pub health: Health,
+    pub blocking: Blocking,
+    pub patrol: Patrol,
+    pub status_effects: StatusEffects,
+    pub grid_pos: GridPos,
+    pub tween: Tween,
+    pub role: Role,
+}
+
+impl LdtkEntity for YetiBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        let fields = &entity_instance.field_instances;
+        let damage = Damage(int_field(fields, "Damage").unwrap_or(50));
+        let health = Health(int_field(fields, "Health").unwrap_or(100));
+
+        // LDtk points count rows from the top of the level
+        let patrol = Patrol {
+            waypoints: points_field(fields, "Patrol")
+                .into_iter()
+                .map(|p| IVec2::new(p.x, layer_instance.c_hei - 1 - p.y))
+                .collect(),
+            ..Default::default()
+        };
+
+        let grid_pos = GridPos::from_entity_info(entity_instance, layer_instance);
+
+        Self {
+            // A hulking block of fur until the yeti gets art of its own, as anything
+            // built from the player's sprites reads as a second player
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb_u8(236, 242, 250),
+                    custom_size: Some(Vec2::new(
+                        layer_instance.grid_size as f32 * 0.8,
+                        layer_instance.grid_size as f32,
+                    )),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            enemy: Enemy::default(),
+            damage,
+            health,
+            blocking: Blocking(true),
+            patrol,
+            status_effects: StatusEffects::default(),
+            grid_pos,
+            tween: Tween::starting_at(grid_pos),
+            role: Role::Enemy,
         }
     }
 }
@@ -300,13 +851,101 @@ pub struct ClimableTileBundle {
     pub climbable: ClimbableTile,
 }
 
+// Counts down the turns left before the tile collapses, once something stood on it.
+// Its position in the world is filled in when it's added to the TileMap.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct CrumblingTile {
+    pub grid_pos: IVec2,
+    pub ticks_left: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct CrumblingTileBundle {
+    pub crumbling: CrumblingTile,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct SlickTile;
+
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct SlickTileBundle {
+    pub slick: SlickTile,
+}
+
 // The actual falling ice, when the player goes underneath
 // the falling ice tile
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
-pub struct FallingIce;
+pub struct FallingIce {
+    // Landed on the player on the way down, rather than being dodged
+    pub has_hit_player: bool,
+}
+
+// Ice still hanging, counting the world ticks it's been up there
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct StaticIce {
+    pub ticks_hanging: u32,
+}
+
+// What makes hanging ice let go
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum IceTrigger {
+    // The player passing underneath it, up to the first wall
+    Proximity,
+    // Every so many world ticks
+    Timed { interval: u32 },
+    // Something standing on the pressure plate at this tile
+    PressurePlate(IVec2),
+}
+
+// How a FallingIce entity behaves, as set up in LDtk.
+// Its home and pressure plate are moved into the world along with it.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+pub struct IceSettings {
+    pub trigger: IceTrigger,
+    // World ticks spent shaking before it falls
+    pub warning_ticks: u32,
+    // Tiles fallen per world tick
+    pub fall_speed: u32,
+    // Whether it goes back to hang where it started once it's gone
+    pub respawns: bool,
+    pub home: IVec2,
+    // Whether it leaves a pile of debris behind when it shatters
+    pub leaves_debris: bool,
+}
+
+impl Default for IceSettings {
+    fn default() -> Self {
+        Self {
+            trigger: IceTrigger::Proximity,
+            warning_ticks: 0,
+            fall_speed: 1,
+            respawns: false,
+            home: IVec2::ZERO,
+            leaves_debris: false,
+        }
+    }
+}
+
+// Hanging ice that's been triggered, shaking until it lets go
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct IceWarning {
+    pub ticks_left: u32,
+}
 
+// A blocking pile of shattered ice, added to the TileMap while it lasts
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
-pub struct StaticIce;
+pub struct Debris {
+    pub ticks_left: u32,
+}
+
+// A purely visual bit of shattered ice, flying off until it fades out
+#[derive(Copy, Clone, PartialEq, Debug, Default, Component)]
+pub struct IceShard {
+    pub velocity: Vec2,
+    pub elapsed: f32,
+    // The tile it flew out of, so it goes away with that tile's level
+    pub grid_pos: IVec2,
+}
 
 #[derive(Clone, Bundle)]
 pub struct FallingIceBundle {
@@ -316,26 +955,995 @@ pub struct FallingIceBundle {
     pub health: Health,
     pub static_ice: StaticIce,
     //pub falling_ice: FallingIce,
+    pub settings: IceSettings,
+    pub inflicts_status: InflictsStatus,
+    pub grid_pos: GridPos,
+    pub tween: Tween,
+    pub role: Role,
 }
 
 impl LdtkEntity for FallingIceBundle {
     fn bundle_entity(
-        _: &EntityInstance,
-        _: &LayerInstance,
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
         _: Option<&Handle<Image>>,
         _: Option<&TilesetDefinition>,
         asset_server: &AssetServer,
         _: &mut Assets<TextureAtlas>,
     ) -> Self {
-        Self {
-            sprite_bundle: SpriteBundle {
-                texture: asset_server.load("FallingIce.png"),
+        let grid_pos = GridPos::from_entity_info(entity_instance, layer_instance);
+
+        let mut settings = IceSettings {
+            home: grid_pos.0,
+            ..Default::default()
+        };
+        let fields = &entity_instance.field_instances;
+
+        let interval = uint_field(fields, "Interval").unwrap_or(4).max(1);
+
+        // LDtk points count rows from the top of the level
+        let pressure_plate = point_field(fields, "PressurePlate")
+            .map(|p| IVec2::new(p.x, layer_instance.c_hei - 1 - p.y));
+
+        if let Some(trigger_value) = enum_field(fields, "Trigger") {
+            settings.trigger = match (trigger_value, pressure_plate) {
+                ("Timed", _) => IceTrigger::Timed { interval },
+                ("PressurePlate", Some(plate)) => IceTrigger::PressurePlate(plate),
+                _ => IceTrigger::Proximity,
+            };
+        }
+
+        if let Some(warning_ticks) = uint_field(fields, "WarningTicks") {
+            settings.warning_ticks = warning_ticks;
+        }
+
+        if let Some(fall_speed) = uint_field(fields, "FallSpeed") {
+            settings.fall_speed = fall_speed.max(1);
+        }
+
+        if let Some(respawns) = bool_field(fields, "Respawns") {
+            settings.respawns = respawns;
+        }
+
+        if let Some(leaves_debris) = bool_field(fields, "LeavesDebris") {
+            settings.leaves_debris = leaves_debris;
+        }
+
+        let damage = Damage(int_field(fields, "Damage").unwrap_or(100));
+
+        Self {
+            sprite_bundle: SpriteBundle {
+                texture: asset_server.load("FallingIce.png"),
                 ..Default::default()
             },
             health: Health(1),
-            damage: Damage(100),
+            damage,
             static_ice: StaticIce::default(),
             //falling_ice: FallingIce::default(),
+            settings,
+            inflicts_status: InflictsStatus::from_fields(entity_instance),
+            grid_pos,
+            tween: Tween::starting_at(grid_pos),
+            role: Role::StaticIce,
+        }
+    }
+}
+
+// Switched on while something stands on it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct PressurePlate {
+    pub is_pressed: bool,
+}
+
+impl PressurePlate {
+    pub fn color(is_pressed: bool) -> Color {
+        if is_pressed {
+            Color::rgba(0.95, 0.8, 0.3, 0.7)
+        } else {
+            Color::rgba(0.95, 0.8, 0.3, 0.3)
+        }
+    }
+}
+
+#[derive(Clone, Bundle)]
+pub struct PressurePlateBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub pressure_plate: PressurePlate,
+    pub switch: Switch,
+    pub grid_pos: GridPos,
+}
+
+impl LdtkEntity for PressurePlateBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: PressurePlate::color(false),
+                    custom_size: Some(Vec2::splat(layer_instance.grid_size as f32)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            pressure_plate: PressurePlate::default(),
+            switch: Switch::from_entity_info(entity_instance, layer_instance),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+        }
+    }
+}
+
+// Levers and pressure plates, and the things they operate.
+// LDtk can't reference other entities here, so the Targets field lists the tiles they're on,
+// moved into the world with the switch and then resolved to the entities found there.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Switch {
+    pub is_on: bool,
+    pub target_tiles: Vec<IVec2>,
+    pub targets: Vec<Entity>,
+}
+
+impl Switch {
+    pub fn from_entity_info(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+    ) -> Self {
+        // LDtk points count rows from the top of the level
+        Switch {
+            target_tiles: points_field(&entity_instance.field_instances, "Targets")
+                .into_iter()
+                .map(|p| IVec2::new(p.x, layer_instance.c_hei - 1 - p.y))
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+// Something operated by switches, active while any of them is on
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Switchable {
+    pub is_active: bool,
+}
+
+// Spikes linked to a switch, which retract while it's on
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Retractable {
+    pub damage: i32,
+}
+
+// Flips between on and off every time the player steps onto it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Lever {
+    pub is_stood_on: bool,
+}
+
+impl Lever {
+    pub fn color(is_on: bool) -> Color {
+        if is_on {
+            Color::rgb(0.4, 0.85, 0.4)
+        } else {
+            Color::rgb(0.85, 0.35, 0.3)
+        }
+    }
+}
+
+#[derive(Clone, Bundle)]
+pub struct LeverBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub lever: Lever,
+    pub switch: Switch,
+    pub grid_pos: GridPos,
+    pub role: Role,
+}
+
+impl LdtkEntity for LeverBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        let grid_size = layer_instance.grid_size as f32;
+
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Lever::color(false),
+                    custom_size: Some(Vec2::new(grid_size / 8.0, grid_size / 2.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            lever: Lever::default(),
+            switch: Switch::from_entity_info(entity_instance, layer_instance),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::Lever,
+        }
+    }
+}
+
+// Shut while it's in the TileMap, switches flip it from how it starts
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Gate {
+    pub starts_open: bool,
+    pub is_open: bool,
+}
+
+impl Gate {
+    pub fn color(is_open: bool) -> Color {
+        if is_open {
+            Color::rgba(0.55, 0.6, 0.7, 0.2)
+        } else {
+            Color::rgb(0.55, 0.6, 0.7)
+        }
+    }
+}
+
+#[derive(Clone, Bundle)]
+pub struct GateBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub gate: Gate,
+    pub switchable: Switchable,
+    pub grid_pos: GridPos,
+}
+
+impl LdtkEntity for GateBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        let starts_open =
+            bool_field(&entity_instance.field_instances, "StartsOpen").unwrap_or(false);
+
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Gate::color(starts_open),
+                    custom_size: Some(Vec2::splat(layer_instance.grid_size as f32)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            gate: Gate {
+                starts_open,
+                is_open: starts_open,
+            },
+            switchable: Switchable::default(),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Key;
+
+#[derive(Clone, Bundle)]
+pub struct KeyBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub key: Key,
+    pub grid_pos: GridPos,
+    pub role: Role,
+}
+
+impl LdtkEntity for KeyBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb_u8(240, 200, 80),
+                    custom_size: Some(Vec2::splat(layer_instance.grid_size as f32 / 3.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            key: Key::default(),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::Key,
+        }
+    }
+}
+
+// Stays shut in the TileMap until the player walks into it with a key
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Door;
+
+#[derive(Clone, Bundle)]
+pub struct DoorBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub door: Door,
+    pub grid_pos: GridPos,
+    pub role: Role,
+}
+
+impl LdtkEntity for DoorBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb_u8(140, 95, 60),
+                    custom_size: Some(Vec2::splat(layer_instance.grid_size as f32)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            door: Door::default(),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::Door,
+        }
+    }
+}
+
+// Optional pickup, counted towards the level's "x/y collected"
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Gem;
+
+#[derive(Clone, Bundle)]
+pub struct GemBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub gem: Gem,
+    pub grid_pos: GridPos,
+    pub role: Role,
+}
+
+impl LdtkEntity for GemBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb_u8(120, 220, 255),
+                    custom_size: Some(Vec2::splat(layer_instance.grid_size as f32 / 3.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            gem: Gem::default(),
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::Gem,
+        }
+    }
+}
+
+// Gems of the current run by level index, remembered by the tile they were placed
+// on so a level that spawns again neither counts nor shows them twice
+#[derive(Clone, Debug, Default)]
+pub struct Collectibles {
+    pub levels: HashMap<usize, LevelCollectibles>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LevelCollectibles {
+    pub placed: HashSet<IVec2>,
+    pub collected: HashSet<IVec2>,
+}
+
+impl Collectibles {
+    pub fn in_level(&self, index: usize) -> (usize, usize) {
+        self.levels
+            .get(&index)
+            .map(|l| (l.collected.len(), l.placed.len()))
+            .unwrap_or_default()
+    }
+
+    pub fn in_run(&self) -> (usize, usize) {
+        self.levels.values().fold((0, 0), |(collected, placed), l| {
+            (collected + l.collected.len(), placed + l.placed.len())
+        })
+    }
+}
+
+// The camera looking at the levels, as opposed to the one drawing the HUD
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct GameCamera;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct HudText;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct SpeedrunTimerText;
+
+// The tile an LDtk entity was placed on, which is how it's recognised again when its
+// level respawns
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Component)]
+pub struct SpawnTile(pub IVec2);
+
+// A campfire that remembers the level as it was when the player last touched it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Checkpoint {
+    // Health to come back with, 0 to keep what the player had when they got here
+    pub health_refill: i32,
+    pub is_lit: bool,
+}
+
+impl Checkpoint {
+    pub fn color(is_lit: bool) -> Color {
+        if is_lit {
+            Color::rgb_u8(255, 140, 40)
+        } else {
+            Color::rgb_u8(90, 70, 60)
+        }
+    }
+}
+
+#[derive(Clone, Bundle)]
+pub struct CheckpointBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub checkpoint: Checkpoint,
+    pub grid_pos: GridPos,
+    pub role: Role,
+}
+
+impl LdtkEntity for CheckpointBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        let mut checkpoint = Checkpoint::default();
+        if let Some(health_refill) = int_field(&entity_instance.field_instances, "HealthRefill") {
+            checkpoint.health_refill = health_refill;
+        }
+
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Checkpoint::color(false),
+                    custom_size: Some(Vec2::new(
+                        layer_instance.grid_size as f32 / 2.0,
+                        layer_instance.grid_size as f32 / 4.0,
+                    )),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            checkpoint,
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::Checkpoint,
+        }
+    }
+}
+
+// What the player had made of a level by the time they reached its checkpoint: what's been
+// taken, opened or destroyed, where blocks were pushed, which way levers were pulled and
+// which ledges have crumbled away. Hazards and enemies still around start over from where
+// the level puts them, and those destroyed before the checkpoint stay gone.
+#[derive(Clone, Debug, Default)]
+pub struct LevelSnapshot {
+    pub level_uid: i32,
+    pub player_pos: IVec2,
+    pub player_health: i32,
+    pub player_keys: u32,
+    pub level_moves: u32,
+    // Entities still in the level, by the tile they spawned on
+    pub entities: HashMap<IVec2, SnapshotEntity>,
+    pub crumbled_tiles: HashSet<IVec2>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SnapshotEntity {
+    pub pos: IVec2,
+    pub switch_is_on: Option<bool>,
+}
+
+// Death respawns the checkpoint's level from LDtk, then puts the snapshot back on top of it
+#[derive(Clone, Debug, Default)]
+pub struct LastCheckpoint {
+    pub snapshot: Option<LevelSnapshot>,
+    pub is_restoring: bool,
+}
+
+// Levels and entities alike keep their LDtk fields by identifier. The typed readers
+// below give None for a field that's missing, empty or of some other type.
+pub fn field<'a>(instances: &'a [ldtk::FieldInstance], identifier: &str) -> Option<&'a FieldValue> {
+    instances
+        .iter()
+        .find(|f| f.identifier == identifier)
+        .map(|f| &f.value)
+}
+
+pub fn int_field(instances: &[ldtk::FieldInstance], identifier: &str) -> Option<i32> {
+    match field(instances, identifier) {
+        Some(FieldValue::Int(value)) => *value,
+        _ => None,
+    }
+}
+
+// Negative values count as 0
+pub fn uint_field(instances: &[ldtk::FieldInstance], identifier: &str) -> Option<u32> {
+    int_field(instances, identifier).map(|value| value.max(0) as u32)
+}
+
+pub fn bool_field(instances: &[ldtk::FieldInstance], identifier: &str) -> Option<bool> {
+    match field(instances, identifier) {
+        Some(FieldValue::Bool(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+pub fn string_field<'a>(instances: &'a [ldtk::FieldInstance], identifier: &str) -> Option<&'a str> {
+    match field(instances, identifier) {
+        Some(FieldValue::String(Some(value))) => Some(value),
+        _ => None,
+    }
+}
+
+pub fn enum_field<'a>(instances: &'a [ldtk::FieldInstance], identifier: &str) -> Option<&'a str> {
+    match field(instances, identifier) {
+        Some(FieldValue::Enum(Some(value))) => Some(value),
+        _ => None,
+    }
+}
+
+// Points are in LDtk's tiles, counting rows from the top of the level
+pub fn point_field(instances: &[ldtk::FieldInstance], identifier: &str) -> Option<IVec2> {
+    match field(instances, identifier) {
+        Some(FieldValue::Point(value)) => *value,
+        _ => None,
+    }
+}
+
+pub fn points_field(instances: &[ldtk::FieldInstance], identifier: &str) -> Vec<IVec2> {
+    match field(instances, identifier) {
+        Some(FieldValue::Points(points)) => points.iter().flatten().copied().collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Reads one of the "Direction" enum values as a step on the grid
+pub fn direction_field(instances: &[ldtk::FieldInstance], identifier: &str) -> Option<IVec2> {
+    match enum_field(instances, identifier)? {
+        "Up" => Some(IVec2::Y),
+        "Down" => Some(-IVec2::Y),
+        "Left" => Some(-IVec2::X),
+        "Right" => Some(IVec2::X),
+        _ => None,
+    }
+}
+
+// Picked up for its health, but only when the player has room for it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct HealthPickup {
+    pub heal: i32,
+}
+
+#[derive(Clone, Bundle)]
+pub struct HealthPickupBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub health_pickup: HealthPickup,
+    pub grid_pos: GridPos,
+    pub role: Role,
+}
+
+impl LdtkEntity for HealthPickupBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb_u8(230, 90, 110),
+                    custom_size: Some(Vec2::splat(layer_instance.grid_size as f32 / 3.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            health_pickup: HealthPickup {
+                heal: int_field(&entity_instance.field_instances, "Heal").unwrap_or(50),
+            },
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::HealthPickup,
+        }
+    }
+}
+
+// Heals the player every turn they end on it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct RestSpot {
+    pub heal_per_turn: i32,
+}
+
+#[derive(Clone, Bundle)]
+pub struct RestSpotBundle {
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub rest_spot: RestSpot,
+    pub grid_pos: GridPos,
+    pub role: Role,
+}
+
+impl LdtkEntity for RestSpotBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb_u8(150, 200, 120),
+                    custom_size: Some(Vec2::new(
+                        layer_instance.grid_size as f32 * 0.75,
+                        layer_instance.grid_size as f32 / 6.0,
+                    )),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            rest_spot: RestSpot {
+                heal_per_turn: int_field(&entity_instance.field_instances, "HealPerTurn")
+                    .unwrap_or(20),
+            },
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+            role: Role::RestSpot,
+        }
+    }
+}
+
+// Sent when a falling player hits the ground or catches a ladder
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PlayerLanded {
+    pub height: u32,
+    pub damage: i32,
+}
+
+// Knocks the camera about for a moment, dying down over time
+#[derive(Clone, Debug, Default)]
+pub struct ScreenShake {
+    pub amplitude: f32,
+    pub time_left: f32,
+    pub offset: Vec2,
+}
+
+// Lingering effects of a hit, each lasting a number of world ticks
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StatusKind {
+    // Turns are spent thawing out instead of acting
+    Frozen,
+    // Health drains away every tick
+    Bleeding,
+    // Input is ignored while the world carries on
+    Stunned,
+}
+
+impl StatusKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Frozen" => Some(StatusKind::Frozen),
+            "Bleeding" => Some(StatusKind::Bleeding),
+            "Stunned" => Some(StatusKind::Stunned),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StatusKind::Frozen => "FROZEN",
+            StatusKind::Bleeding => "BLEEDING",
+            StatusKind::Stunned => "STUNNED",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    pub ticks_left: u32,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct StatusEffects(pub Vec<StatusEffect>);
+
+impl StatusEffects {
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.0.iter().any(|effect| effect.kind == kind)
+    }
+
+    // Getting hit with an effect that's already there only tops it back up
+    pub fn add(&mut self, effect: StatusEffect) {
+        match self.0.iter_mut().find(|e| e.kind == effect.kind) {
+            Some(existing) => existing.ticks_left = existing.ticks_left.max(effect.ticks_left),
+            None => self.0.push(effect),
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for effect in self.0.iter_mut() {
+            effect.ticks_left = effect.ticks_left.saturating_sub(1);
         }
+        self.0.retain(|effect| effect.ticks_left > 0);
+    }
+}
+
+// The effect a hazard leaves on whoever it hits, from its "Inflicts" and "InflictTicks" fields
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct InflictsStatus(pub Option<StatusEffect>);
+
+impl InflictsStatus {
+    fn from_fields(entity_instance: &EntityInstance) -> Self {
+        let fields = &entity_instance.field_instances;
+        let kind = enum_field(fields, "Inflicts").and_then(StatusKind::from_name);
+        let ticks_left = uint_field(fields, "InflictTicks").unwrap_or(3).max(1);
+
+        InflictsStatus(kind.map(|kind| StatusEffect { kind, ticks_left }))
+    }
+}
+
+// Paces the world while a stunned player can't move it along themselves
+pub struct StunnedTurnTimer(pub Timer);
+
+impl Default for StunnedTurnTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(0.5, true))
+    }
+}
+
+// Gusts that blow the player a tile sideways every few world ticks while they're inside
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct WindZone {
+    pub direction: IVec2,
+    // In tiles, going right and down from the zone's GridPos
+    pub size: IVec2,
+    pub interval: u32,
+    pub ticks_until_gust: u32,
+}
+
+impl WindZone {
+    pub fn contains(&self, zone_pos: IVec2, grid_pos: IVec2) -> bool {
+        let offset = grid_pos - zone_pos;
+        (0..self.size.x).contains(&offset.x) && (0..self.size.y).contains(&-offset.y)
+    }
+}
+
+#[derive(Clone, Bundle)]
+pub struct WindZoneBundle {
+    pub wind_zone: WindZone,
+    pub grid_pos: GridPos,
+}
+
+impl LdtkEntity for WindZoneBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> Self {
+        // Wind only ever blows sideways
+        let fields = &entity_instance.field_instances;
+        let direction = direction_field(fields, "Direction").unwrap_or(IVec2::X);
+        let interval = uint_field(fields, "Interval").unwrap_or(3).max(1);
+
+        Self {
+            wind_zone: WindZone {
+                direction: IVec2::new(direction.x, 0),
+                size: IVec2::new(
+                    entity_instance.width / layer_instance.grid_size,
+                    entity_instance.height / layer_instance.grid_size,
+                ),
+                interval,
+                ticks_until_gust: interval,
+            },
+            grid_pos: GridPos::from_entity_info(entity_instance, layer_instance),
+        }
+    }
+}
+
+// Drifts about inside a wind zone to show which way it blows
+#[derive(Copy, Clone, PartialEq, Debug, Default, Component)]
+pub struct Snowflake {
+    pub speed: f32,
+}
+
+// Sent whenever a wind zone gusts in the level the player is in
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct WindGusted {
+    pub pushed_player: bool,
+}
+
+// How strongly the wind is blowing right now, from 0 when calm to 1 at the height of a gust
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct WindStrength(pub f32);
+
+// How the last level to be finished went, for the results screen
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct LevelResult {
+    pub level_index: usize,
+    pub level_name: String,
+    pub moves: u32,
+    pub par: u32,
+    pub stars: Option<u32>,
+    pub is_best: bool,
+    // Moves and stars, the best there's been for the level including this time
+    pub best: Option<(u32, Option<u32>)>,
+    pub damage_taken: i32,
+    // Collected and placed
+    pub gems: (usize, usize),
+    pub deaths: u32,
+    // In seconds
+    pub time: f32,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LevelResultsUi;
+
+// How the whole climb has gone, kept around for the credits after the game state is reset
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct RunStats {
+    pub advantage: Option<Advantage>,
+    pub moves: u32,
+    pub deaths: u32,
+    pub damage_dealt: i32,
+    pub damage_taken: i32,
+    pub ice_dodged: u32,
+    pub obstacles_destroyed: u32,
+    // Seconds since startup when the run began, and how long it took from there to the top
+    pub started_at: f64,
+    pub time: f64,
+}
+
+// The credits rolling up the credits screen, and how far up they've gone in pixels
+#[derive(Copy, Clone, PartialEq, Debug, Default, Component)]
+pub struct RollingCredits {
+    pub scrolled: f32,
+}
+
+// Times the run for racing, shown only once it's been switched on
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SpeedrunTimer {
+    pub is_shown: bool,
+    // One per level finished so far this run
+    pub splits: Vec<Split>,
+}
+
+// A level made up by the generator, played on its own
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GeneratedLevel {
+    Daily(DailyChallenge),
+    // A new one every time
+    Random {
+        seed: u64,
+        settings: GeneratorSettings,
+    },
+}
+
+impl GeneratedLevel {
+    pub fn seed(&self) -> u64 {
+        match self {
+            GeneratedLevel::Daily(daily_challenge) => daily_challenge.seed(),
+            GeneratedLevel::Random { seed, .. } => *seed,
+        }
+    }
+
+    pub fn settings(&self) -> GeneratorSettings {
+        match self {
+            GeneratedLevel::Daily(_) => GeneratorSettings::default(),
+            GeneratedLevel::Random { settings, .. } => *settings,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            GeneratedLevel::Daily(daily_challenge) => format!("Daily {}", daily_challenge.date()),
+            GeneratedLevel::Random { seed, .. } => format!("Climb {}", seed),
+        }
+    }
+}
+
+// Everyone playing on the same day gets the same generated level, and the same advantage
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DailyChallenge {
+    // Days since the start of 1970, in UTC
+    pub day: i64,
+}
+
+impl DailyChallenge {
+    pub fn today() -> Self {
+        Self {
+            day: days_since_epoch(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.day as u64
+    }
+
+    // As year-month-day
+    pub fn date(&self) -> String {
+        // Howard Hinnant's days to civil date conversion
+        let z = self.day + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn days_since_epoch() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since_epoch| (since_epoch.as_secs() / 86_400) as i64)
+}
+
+// There's no system clock to ask on the web
+#[cfg(target_arch = "wasm32")]
+fn days_since_epoch() -> i64 {
+    (js_sys::Date::now() / 86_400_000.0).floor() as i64
+}
+
+// The LDtk project a generated level is made to look like, until it's loaded and the
+// level can be generated
+pub struct PendingGeneratedLevel(pub Handle<LdtkAsset>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daily_challenge_date() {
+        assert_eq!(DailyChallenge { day: 0 }.date(), "1970-01-01");
+        assert_eq!(DailyChallenge { day: 19_056 }.date(), "2022-03-05");
+        assert_eq!(DailyChallenge { day: 20_745 }.date(), "2026-10-19");
     }
 }
@@ -0,0 +1,939 @@
+use crate::components::*;
+use crate::systems::{apply_gravity, can_step, is_falling_past_ladder, landing_damage};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::ldtk;
+use bevy_ecs_ldtk::prelude::FieldValue;
+use serde_json::json;
+
+use std::collections::{HashSet, VecDeque};
+
+// Levels are built out of the same walls, ladders and entities as the hand-made ones,
+// then run through the turn rules to make sure they can be climbed. The same seed and
+// settings always make the same level, on every platform.
+
+// Rows climbed from one floor to the next
+const MIN_FLOOR_GAP: i32 = 3;
+const MAX_FLOOR_GAP: i32 = 4;
+const MIN_PLATFORM_LENGTH: i32 = 4;
+const MAX_PLATFORM_LENGTH: i32 = 10;
+// Enough for the ground and one floor above it
+const MIN_HEIGHT: i32 = MAX_FLOOR_GAP + 3;
+// Seeds tried before giving up on making a level that can be climbed
+const MAX_ATTEMPTS: u32 = 16;
+// How a hazard standing on a floor is picked between spikes and blocks
+const SPIKE_WEIGHT: i32 = 5;
+const BLOCK_WEIGHT: i32 = 2;
+
+// What ice hits for when the project has none to copy the damage of
+const DEFAULT_ICE_DAMAGE: i32 = 100;
+
+// Which tiles of the tileset draw what
+const LADDER_TOP_TILE: i32 = 3;
+const LADDER_MIDDLE_TILE: i32 = 9;
+const LADDER_BOTTOM_TILE: i32 = 15;
+const BACKGROUND_TILE: i32 = 5;
+
+// SplitMix64, small and fully specified so a seed means the same numbers on native
+// and wasm builds, whatever version of the rand crate is around
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // In low..high, which has to be a non-empty range
+    pub fn range(&mut self, low: i32, high: i32) -> i32 {
+        low + (self.next_u64() % (high - low) as u64) as i32
+    }
+
+    pub fn chance(&mut self, percent: u32) -> bool {
+        self.next_u64() % 100 < percent as u64
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Cell {
+    Empty,
+    Wall,
+    Ladder,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Piece {
+    Player,
+    Goal,
+    ObstacleSpike,
+    ObstacleBlock,
+    FallingIce,
+}
+
+impl Piece {
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            Piece::Player => "Player",
+            Piece::Goal => "Goal",
+            Piece::ObstacleSpike => "ObstacleSpike",
+            Piece::ObstacleBlock => "ObstacleBlock",
+            Piece::FallingIce => "FallingIce",
+        }
+    }
+}
+
+// A level before it's turned into LDtk's format. Positions are in tiles from the
+// bottom-left, like everywhere else in the game.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Layout {
+    pub width: i32,
+    pub height: i32,
+    cells: Vec<Cell>,
+    pub pieces: Vec<(Piece, IVec2)>,
+}
+
+impl Layout {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::Empty; (width * height) as usize],
+            pieces: Vec::new(),
+        }
+    }
+
+    pub fn contains(&self, pos: IVec2) -> bool {
+        pos.x >= 0 && pos.y >= 0 && pos.x < self.width && pos.y < self.height
+    }
+
+    pub fn cell(&self, pos: IVec2) -> Cell {
+        if self.contains(pos) {
+            self.cells[(pos.y * self.width + pos.x) as usize]
+        } else {
+            Cell::Empty
+        }
+    }
+
+    pub fn set_cell(&mut self, pos: IVec2, cell: Cell) {
+        if self.contains(pos) {
+            self.cells[(pos.y * self.width + pos.x) as usize] = cell;
+        }
+    }
+
+    pub fn piece_at(&self, pos: IVec2) -> Option<Piece> {
+        self.pieces
+            .iter()
+            .find(|(_, piece_pos)| *piece_pos == pos)
+            .map(|(piece, _)| *piece)
+    }
+
+    pub fn find(&self, piece: Piece) -> Option<IVec2> {
+        self.pieces
+            .iter()
+            .find(|(p, _)| *p == piece)
+            .map(|(_, pos)| *pos)
+    }
+
+    pub fn tile_map(&self) -> TileMap {
+        let mut tile_map = TileMap::default();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pos = IVec2::new(x, y);
+                match self.cell(pos) {
+                    Cell::Wall => {
+                        tile_map.0.insert(pos, TileType::Wall);
+                    }
+                    Cell::Ladder => {
+                        tile_map.0.insert(pos, TileType::Ladder);
+                    }
+                    Cell::Empty => {}
+                }
+            }
+        }
+        tile_map
+    }
+}
+
+// How hard a generated level is
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GeneratorSettings {
+    // In tiles
+    pub width: i32,
+    pub height: i32,
+    // Chance from 0 to 1 of a hazard at each spot one could go
+    pub hazard_density: f32,
+    // Chance from 0 to 1 of each other column two floors share getting a ladder too
+    pub ladder_ratio: f32,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        Self {
+            width: 16,
+            height: 16,
+            hazard_density: 0.3,
+            ladder_ratio: 0.1,
+        }
+    }
+}
+
+// Floats only go as far as whole percents, so the same settings make the same level anywhere
+fn percent(ratio: f32) -> u32 {
+    (ratio.clamp(0.0, 1.0) * 100.0).round() as u32
+}
+
+// A level that can be climbed, with the way up it. If the seed's layout can't be, the
+// seeds after it are tried, each derived from the last so a seed always makes the same
+// level. None if none of them could.
+pub fn generate_layout(
+    seed: u64,
+    settings: &GeneratorSettings,
+    rules: &LevelRules,
+    max_health: i32,
+    ice_damage: i32,
+) -> Option<(Layout, Solution)> {
+    let mut attempt_seed = seed;
+    for _ in 0..MAX_ATTEMPTS {
+        let layout = lay_out(attempt_seed, settings, rules, max_health, ice_damage);
+        if let Some(solution) = solve(&layout, rules, max_health, ice_damage) {
+            return Some((layout, solution));
+        }
+        attempt_seed = SeededRng::new(attempt_seed).next_u64();
+    }
+    None
+}
+
+// Floors stacked up the level, each reached from the one below by a ladder, with
+// hazards scattered over them wherever the climb stays possible
+fn lay_out(
+    seed: u64,
+    settings: &GeneratorSettings,
+    rules: &LevelRules,
+    max_health: i32,
+    ice_damage: i32,
+) -> Layout {
+    let mut rng = SeededRng::new(seed);
+    let width = settings.width.max(MIN_PLATFORM_LENGTH);
+    let height = settings.height.max(MIN_HEIGHT);
+    let mut layout = Layout::new(width, height);
+
+    for x in 0..width {
+        layout.set_cell(IVec2::new(x, 0), Cell::Wall);
+    }
+
+    // Each floor as its row and the columns it spans
+    let mut floors = vec![(0, 0, width - 1)];
+    loop {
+        let (y, start, end) = *floors.last().unwrap();
+        let next_y = y + rng.range(MIN_FLOOR_GAP, MAX_FLOOR_GAP + 1);
+        // Leave room above the top floor to stand on it
+        if next_y > height - 3 {
+            break;
+        }
+
+        let ladder_x = rng.range(start, end + 1);
+        let length = rng.range(MIN_PLATFORM_LENGTH, MAX_PLATFORM_LENGTH.min(width) + 1);
+        let next_start = (ladder_x - rng.range(0, length)).clamp(0, width - length);
+        let next_end = next_start + length - 1;
+        for x in next_start..=next_end {
+            layout.set_cell(IVec2::new(x, next_y), Cell::Wall);
+        }
+
+        // There's always one way up, and maybe more where the floors overlap
+        for x in start.max(next_start)..=end.min(next_end) {
+            if x != ladder_x && !rng.chance(percent(settings.ladder_ratio)) {
+                continue;
+            }
+            // From standing on this floor to standing on top of the next one
+            for ladder_y in y + 1..=next_y + 1 {
+                layout.set_cell(IVec2::new(x, ladder_y), Cell::Ladder);
+            }
+        }
+
+        floors.push((next_y, next_start, next_end));
+    }
+
+    let standing_spots = |layout: &Layout, (y, start, end): (i32, i32, i32)| {
+        (start..=end)
+            .map(move |x| IVec2::new(x, y + 1))
+            .filter(|pos| layout.cell(*pos) == Cell::Empty)
+            .collect::<Vec<IVec2>>()
+    };
+
+    // On a ladder top if the floor is nothing but ladders
+    let pick_spot = |layout: &Layout, rng: &mut SeededRng, (y, start, end): (i32, i32, i32)| {
+        let mut spots = standing_spots(layout, (y, start, end));
+        if spots.is_empty() {
+            spots = (start..=end).map(|x| IVec2::new(x, y + 1)).collect();
+        }
+        spots[rng.range(0, spots.len() as i32) as usize]
+    };
+
+    let player_pos = pick_spot(&layout, &mut rng, floors[0]);
+    layout.pieces.push((Piece::Player, player_pos));
+
+    let goal_pos = pick_spot(&layout, &mut rng, *floors.last().unwrap());
+    layout.pieces.push((Piece::Goal, goal_pos));
+
+    // Spikes and blocks sit on the floors, ice hangs under them
+    let mut candidates = Vec::new();
+    for (i, floor) in floors.iter().enumerate() {
+        for pos in standing_spots(&layout, *floor) {
+            if layout.piece_at(pos).is_none() {
+                candidates.push((true, pos));
+            }
+        }
+        if i > 0 {
+            let (y, start, end) = *floor;
+            for x in start..=end {
+                let pos = IVec2::new(x, y - 1);
+                if layout.cell(pos) == Cell::Empty && layout.cell(pos + IVec2::Y) == Cell::Wall {
+                    candidates.push((false, pos));
+                }
+            }
+        }
+    }
+
+    for (is_on_floor, pos) in candidates {
+        if !rng.chance(percent(settings.hazard_density)) {
+            continue;
+        }
+        let piece = if !is_on_floor {
+            Piece::FallingIce
+        } else if rng.range(0, SPIKE_WEIGHT + BLOCK_WEIGHT) < SPIKE_WEIGHT {
+            Piece::ObstacleSpike
+        } else {
+            Piece::ObstacleBlock
+        };
+        layout.pieces.push((piece, pos));
+        if solve(&layout, rules, max_health, ice_damage).is_none() {
+            layout.pieces.pop();
+        }
+    }
+
+    layout
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Solution {
+    pub moves: u32,
+    pub damage_taken: i32,
+}
+
+// The fewest moves from the player to the goal that they live through, following the
+// same step, gravity and landing rules as the game. Spikes and blocks are never walked
+// into, and standing anywhere ice could fall on costs a hit from it.
+pub fn solve(
+    layout: &Layout,
+    rules: &LevelRules,
+    max_health: i32,
+    ice_damage: i32,
+) -> Option<Solution> {
+    let start = layout.find(Piece::Player)?;
+    let goal = layout.find(Piece::Goal)?;
+
+    let tile_map = layout.tile_map();
+    let mut loaded_levels = LoadedLevels::default();
+    loaded_levels.levels.insert(
+        0,
+        LevelDimensions {
+            index: 0,
+            origin: IVec2::ZERO,
+            width: layout.width,
+            height: layout.height,
+        },
+    );
+    loaded_levels.rules.insert(0, *rules);
+
+    let mut blocked = HashSet::new();
+    let mut under_ice = HashSet::new();
+    for (piece, pos) in layout.pieces.iter() {
+        match piece {
+            Piece::ObstacleSpike | Piece::ObstacleBlock => {
+                blocked.insert(*pos);
+            }
+            Piece::FallingIce => {
+                let mut ice_pos = *pos;
+                while layout.contains(ice_pos) && layout.cell(ice_pos) != Cell::Wall {
+                    under_ice.insert(ice_pos);
+                    ice_pos -= IVec2::Y;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Where the player is, how far they've been falling, and how hurt they are
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert((start, 0, 0));
+    queue.push_back((start, 0, 0, 0));
+
+    while let Some((pos, fall_height, damage_taken, moves)) = queue.pop_front() {
+        for direction in [IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y] {
+            let mut game_state = GameState {
+                player_fall_height: fall_height,
+                ..Default::default()
+            };
+            let is_falling = !tile_map.0.contains_key(&(pos - IVec2::Y))
+                || is_falling_past_ladder(&tile_map, &loaded_levels, &game_state, pos);
+            let new_pos = pos + direction;
+            if !can_step(&tile_map, &loaded_levels, pos, new_pos, is_falling) {
+                continue;
+            }
+
+            let mut player_pos = GridPos(new_pos);
+            let mut player_tween = Tween::starting_at(player_pos);
+            let mut player_health = Health(max_health - damage_taken);
+            apply_gravity(
+                &tile_map,
+                &loaded_levels,
+                &mut game_state,
+                pos,
+                &mut player_pos,
+                &mut player_tween,
+                &mut player_health,
+            );
+            if player_health.0 <= 0 || blocked.contains(&player_pos.0) {
+                continue;
+            }
+
+            let mut new_damage_taken = damage_taken;
+            if !game_state.player_is_falling && game_state.player_fall_height > 0 {
+                new_damage_taken += landing_damage(
+                    &tile_map,
+                    rules,
+                    player_pos.0,
+                    game_state.player_fall_height,
+                    player_health.0,
+                );
+                game_state.player_fall_height = 0;
+            }
+            if under_ice.contains(&player_pos.0) {
+                new_damage_taken += ice_damage;
+            }
+            if new_damage_taken >= max_health {
+                continue;
+            }
+
+            if player_pos.0 == goal {
+                return Some(Solution {
+                    moves: moves + 1,
+                    damage_taken: new_damage_taken,
+                });
+            }
+
+            let state = (
+                player_pos.0,
+                game_state.player_fall_height,
+                new_damage_taken,
+            );
+            if visited.insert(state) {
+                queue.push_back((
+                    player_pos.0,
+                    game_state.player_fall_height,
+                    new_damage_taken,
+                    moves + 1,
+                ));
+            }
+        }
+    }
+
+    None
+}
+
+// Turns a layout into a level of the given LDtk project, drawn and set up like the
+// hand-made levels in it. Entities are copies of how they're most often set up there.
+pub fn build_level(
+    layout: &Layout,
+    project: &ldtk::LdtkJson,
+    uid: i32,
+    identifier: &str,
+    name: &str,
+    par: u32,
+) -> Option<ldtk::Level> {
+    let template = project.levels.first()?;
+    let mut level = template.clone();
+    level.uid = uid;
+    level.identifier = identifier.to_string();
+    level.neighbours.clear();
+    level.world_x = 0;
+    level.world_y = 0;
+
+    // LDtk shows what's in the editor values, the game reads the plain ones
+    for field_instance in level.field_instances.iter_mut() {
+        let (value, editor_value) = match field_instance.identifier.as_str() {
+            "Name" => (
+                FieldValue::String(Some(name.to_string())),
+                Some(json!({ "id": "V_String", "params": [name] })),
+            ),
+            "Par" => (
+                FieldValue::Int(Some(par as i32)),
+                Some(json!({ "id": "V_Int", "params": [par] })),
+            ),
+            "MoveLimit" => (FieldValue::Int(Some(0)), None),
+            _ => continue,
+        };
+        field_instance.value = value;
+        field_instance.real_editor_values = match editor_value {
+            Some(editor_value) => vec![Some(editor_value)],
+            None => Vec::new(),
+        };
+    }
+
+    let grid_size = template
+        .layer_instances
+        .iter()
+        .flatten()
+        .map(|layer| layer.grid_size)
+        .next()?;
+    level.px_wid = layout.width * grid_size;
+    level.px_hei = layout.height * grid_size;
+
+    // LDtk counts rows from the top
+    let to_ldtk = |pos: IVec2| IVec2::new(pos.x, layout.height - 1 - pos.y);
+    let tile = |pos: IVec2, t: i32, tileset_width: i32| {
+        let cell = to_ldtk(pos);
+        ldtk::TileInstance {
+            d: vec![cell.y * layout.width + cell.x],
+            f: 0,
+            px: cell * grid_size,
+            src: IVec2::new(t % tileset_width, t / tileset_width) * grid_size,
+            t,
+        }
+    };
+    let positions: Vec<IVec2> = (0..layout.height)
+        .rev()
+        .flat_map(|y| (0..layout.width).map(move |x| IVec2::new(x, y)))
+        .collect();
+
+    for layer in level.layer_instances.iter_mut().flatten() {
+        layer.c_wid = layout.width;
+        layer.c_hei = layout.height;
+        layer.level_id = uid;
+        layer.auto_layer_tiles.clear();
+        layer.grid_tiles.clear();
+        layer.entity_instances.clear();
+
+        let tileset_width = layer
+            .tileset_def_uid
+            .and_then(|tileset_uid| {
+                project
+                    .defs
+                    .tilesets
+                    .iter()
+                    .find(|tileset| tileset.uid == tileset_uid)
+            })
+            .map_or(1, |tileset| tileset.c_wid.max(1));
+
+        match layer.identifier.as_str() {
+            "Terrain" => {
+                layer.int_grid_csv = positions
+                    .iter()
+                    .map(|pos| match layout.cell(*pos) {
+                        Cell::Empty => 0,
+                        Cell::Wall => 1,
+                        Cell::Ladder => 2,
+                    })
+                    .collect();
+                layer.int_grid = Some(
+                    layer
+                        .int_grid_csv
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, v)| **v != 0)
+                        .map(|(coord_id, v)| ldtk::IntGridValueInstance {
+                            coord_id: coord_id as i32,
+                            v: v - 1,
+                        })
+                        .collect(),
+                );
+            }
+            "Terrain_baked" => {
+                for pos in positions.iter() {
+                    let is_rock =
+                        |pos: IVec2| !layout.contains(pos) || layout.cell(pos) == Cell::Wall;
+                    let has_rock_beside = is_rock(*pos - IVec2::X) || is_rock(*pos + IVec2::X);
+                    // Ladders through a floor have its rock behind them
+                    let is_drawn = match layout.cell(*pos) {
+                        Cell::Wall => true,
+                        Cell::Ladder => has_rock_beside,
+                        Cell::Empty => false,
+                    };
+                    if is_drawn {
+                        let t = wall_tile(
+                            is_rock(*pos + IVec2::Y),
+                            is_rock(*pos - IVec2::Y),
+                            is_rock(*pos - IVec2::X),
+                            is_rock(*pos + IVec2::X),
+                        );
+                        layer.grid_tiles.push(tile(*pos, t, tileset_width));
+                    }
+                }
+            }
+            "Interactables_baked" => {
+                for pos in positions.iter() {
+                    if layout.cell(*pos) != Cell::Ladder {
+                        continue;
+                    }
+                    let t = match (
+                        layout.cell(*pos + IVec2::Y) == Cell::Ladder,
+                        layout.cell(*pos - IVec2::Y) == Cell::Ladder,
+                    ) {
+                        (true, true) => LADDER_MIDDLE_TILE,
+                        (true, false) => LADDER_BOTTOM_TILE,
+                        (false, _) => LADDER_TOP_TILE,
+                    };
+                    layer.grid_tiles.push(tile(*pos, t, tileset_width));
+                }
+            }
+            "Background" => {
+                for pos in positions.iter() {
+                    layer
+                        .grid_tiles
+                        .push(tile(*pos, BACKGROUND_TILE, tileset_width));
+                }
+            }
+            "Entities" => {
+                for (piece, pos) in layout.pieces.iter() {
+                    let mut entity_instance = prototype(project, piece.identifier())?;
+                    let cell = to_ldtk(*pos);
+                    entity_instance.grid = cell;
+                    entity_instance.px = cell * grid_size;
+                    layer.entity_instances.push(entity_instance);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(level)
+}
+
+// The most common setup of an entity across the project is taken as its plain one
+fn prototype(project: &ldtk::LdtkJson, identifier: &str) -> Option<ldtk::EntityInstance> {
+    let instances: Vec<&ldtk::EntityInstance> = project
+        .levels
+        .iter()
+        .flat_map(|level| level.layer_instances.iter().flatten())
+        .flat_map(|layer| layer.entity_instances.iter())
+        .filter(|entity_instance| entity_instance.identifier == identifier)
+        .collect();
+
+    instances
+        .iter()
+        .max_by_key(|a| {
+            instances
+                .iter()
+                .filter(|b| b.field_instances == a.field_instances)
+                .count()
+        })
+        .map(|entity_instance| (*entity_instance).clone())
+}
+
+// The damage generated ice does, copied from the hand-made ice like the rest of its setup
+pub fn ice_damage(project: &ldtk::LdtkJson) -> i32 {
+    prototype(project, "FallingIce")
+        .and_then(|entity_instance| int_field(&entity_instance.field_instances, "Damage"))
+        .unwrap_or(DEFAULT_ICE_DAMAGE)
+}
+
+// Picks a rock tile by which sides have more rock, the way the hand-made levels are drawn
+fn wall_tile(up: bool, down: bool, left: bool, right: bool) -> i32 {
+    match (up, down, left, right) {
+        (false, false, false, _) => 31,
+        (false, false, true, false) => 33,
+        (false, false, true, true) => 32,
+        (false, true, false, false) => 13,
+        (false, true, false, true) => 23,
+        (false, true, true, false) => 33,
+        (false, true, true, true) => 26,
+        (true, false, false, false) => 30,
+        (true, false, false, true) => 27,
+        (true, false, true, false) => 29,
+        (true, true, true, false) => 16,
+        _ => 19,
+    }
+}
+
+pub const EXPORT_COMMAND: &str = "generate-levels";
+const EXPORT_USAGE: &str = "usage: bevy-jam-1 generate-levels <project.ldtk> [--count N] \
+[--seed N] [--width N] [--height N] [--hazards RATIO] [--ladders RATIO]";
+// Exported levels are checked against a player without any advantage
+const EXPORT_HEALTH: i32 = 100;
+// Space left between exported levels so they aren't taken as neighbours
+const EXPORT_GAP: i32 = 256;
+
+// Adds generated levels to the end of an LDtk project for designers to polish. Only the
+// levels and the next uid are written, the rest of the file is left as LDtk saved it.
+pub fn export_levels(args: &[String]) -> Result<String, String> {
+    let mut args = args.iter();
+    let path = args.next().ok_or(EXPORT_USAGE)?;
+    let mut count: u32 = 1;
+    let mut seed = rand::random::<u32>() as u64;
+    let mut settings = GeneratorSettings::default();
+    while let Some(option) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value\n{}", option, EXPORT_USAGE))?;
+        let invalid = || format!("{} can't be {}", option, value);
+        match option.as_str() {
+            "--count" => count = value.parse().map_err(|_| invalid())?,
+            "--seed" => seed = value.parse().map_err(|_| invalid())?,
+            "--width" => settings.width = value.parse().map_err(|_| invalid())?,
+            "--height" => settings.height = value.parse().map_err(|_| invalid())?,
+            "--hazards" => settings.hazard_density = value.parse().map_err(|_| invalid())?,
+            "--ladders" => settings.ladder_ratio = value.parse().map_err(|_| invalid())?,
+            _ => return Err(format!("unknown option {}\n{}", option, EXPORT_USAGE)),
+        }
+    }
+    if count == 0 {
+        return Err(format!("--count has to be at least 1\n{}", EXPORT_USAGE));
+    }
+
+    let mut text =
+        std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    let project: ldtk::LdtkJson =
+        serde_json::from_str(&text).map_err(|e| format!("could not parse {}: {}", path, e))?;
+    let rules = project
+        .levels
+        .first()
+        .map(LevelRules::from_level)
+        .unwrap_or_default();
+    let ice_damage = ice_damage(&project);
+
+    let mut world_x = project
+        .levels
+        .iter()
+        .map(|level| level.world_x + level.px_wid)
+        .max()
+        .unwrap_or(0);
+    let mut new_levels = String::new();
+    let mut summary = Vec::new();
+    for i in 0..count {
+        let level_seed = seed.wrapping_add(i as u64);
+        let (layout, solution) =
+            generate_layout(level_seed, &settings, &rules, EXPORT_HEALTH, ice_damage)
+                .ok_or_else(|| format!("could not make a level with seed {}", level_seed))?;
+        let par = solution.moves;
+        let identifier = format!("Generated_{}", level_seed);
+        let mut level = build_level(
+            &layout,
+            &project,
+            project.next_uid + i as i32,
+            &identifier,
+            &format!("Generated {}", level_seed),
+            par,
+        )
+        .ok_or_else(|| format!("{} has no level to copy the setup of", path))?;
+
+        // Side by side, standing on the same ground as the first level
+        level.world_x = world_x + EXPORT_GAP;
+        level.world_y = -level.px_hei;
+        world_x = level.world_x + level.px_wid;
+
+        let level_json = serde_json::to_string_pretty(&level).map_err(|e| e.to_string())?;
+        new_levels += &level_json;
+        new_levels += ",\n";
+        summary.push(format!("{} (par {})", identifier, par));
+    }
+
+    // Never leave the project with a dangling comma or a next uid that wasn't used
+    if new_levels.is_empty() {
+        return Err(format!("no levels were generated for {}", path));
+    }
+    text = insert_levels(&text, new_levels.trim_end_matches(",\n"))
+        .and_then(|text| replace_next_uid(&text, project.next_uid + count as i32))
+        .ok_or_else(|| format!("could not find where the levels go in {}", path))?;
+    std::fs::write(path, text).map_err(|e| format!("could not write {}: {}", path, e))?;
+
+    Ok(format!("added {} to {}", summary.join(", "), path))
+}
+
+// Puts levels at the end of the project's `levels` array
+fn insert_levels(text: &str, levels: &str) -> Option<String> {
+    let key = text.find("\"levels\":")?;
+    let start = key + text[key..].find('[')?;
+
+    // Find the bracket closing it, minding brackets inside strings
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut is_escaped = false;
+    let mut end = None;
+    for (i, c) in text[start..].char_indices() {
+        if in_string {
+            match c {
+                _ if is_escaped => is_escaped = false,
+                '\\' => is_escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => depth += 1,
+            ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(start + i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let end = end?;
+
+    let existing = text[start + 1..end].trim_end();
+    let separator = if existing.trim().is_empty() { "" } else { "," };
+    Some(format!(
+        "{}{}\n{}\n{}",
+        &text[..start + 1 + existing.len()],
+        separator,
+        levels,
+        &text[end..]
+    ))
+}
+
+fn replace_next_uid(text: &str, next_uid: i32) -> Option<String> {
+    let key = text.find("\"nextUid\":")? + "\"nextUid\":".len();
+    let start = key + text[key..].find(|c: char| c.is_ascii_digit())?;
+    let end = start
+        + text[start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len() - start);
+    Some(format!("{}{}{}", &text[..start], next_uid, &text[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Top row first, the way the level looks in LDtk
+    fn render(layout: &Layout) -> String {
+        let mut rows = Vec::new();
+        for y in (0..layout.height).rev() {
+            let row: String = (0..layout.width)
+                .map(|x| {
+                    let pos = IVec2::new(x, y);
+                    match (layout.piece_at(pos), layout.cell(pos)) {
+                        (Some(Piece::Player), _) => 'P',
+                        (Some(Piece::Goal), _) => 'G',
+                        (Some(Piece::ObstacleSpike), _) => '^',
+                        (Some(Piece::ObstacleBlock), _) => 'B',
+                        (Some(Piece::FallingIce), _) => 'V',
+                        (None, Cell::Wall) => '#',
+                        (None, Cell::Ladder) => 'H',
+                        (None, Cell::Empty) => '.',
+                    }
+                })
+                .collect();
+            rows.push(row);
+        }
+        rows.join("\n")
+    }
+
+    // Seeds are shared as daily challenges and par is saved against them, so any change
+    // to the level a seed makes has to be on purpose
+    #[test]
+    fn seed_makes_the_same_level() {
+        let rules = LevelRules::default();
+        let (layout, solution) =
+            generate_layout(1, &GeneratorSettings::default(), &rules, 100, 100).unwrap();
+        let expected = [
+            "................",
+            "................",
+            "................",
+            "......^^H..GB...",
+            "......##H#######",
+            "........H..V....",
+            "........H.......",
+            ".......HH.......",
+            ".......H####....",
+            ".......HV.......",
+            ".......HB..B....",
+            ".......H####....",
+            ".......HV.......",
+            ".......H........",
+            "B..BP..H........",
+            "################",
+        ];
+        assert_eq!(render(&layout), expected.join("\n"));
+        assert_eq!(
+            solution,
+            Solution {
+                moves: 18,
+                damage_taken: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn levels_go_into_an_empty_array() {
+        let text = insert_levels(r#"{ "levels": [], "nextUid": 5 }"#, r#"{ "uid": 5 }"#)
+            .and_then(|text| replace_next_uid(&text, 6))
+            .unwrap();
+        let project: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(project["levels"], json!([{ "uid": 5 }]));
+        assert_eq!(project["nextUid"], 6);
+    }
+
+    #[test]
+    fn brackets_and_quotes_in_strings_are_skipped() {
+        let text = r#"{
+    "levels": [{ "identifier": "a]\"[b", "tags": ["}"] }],
+    "after": "]"
+}"#;
+        let text = insert_levels(text, r#"{ "identifier": "new" }"#).unwrap();
+        let project: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            project["levels"],
+            json!([
+                { "identifier": "a]\"[b", "tags": ["}"] },
+                { "identifier": "new" },
+            ])
+        );
+        assert_eq!(project["after"], "]");
+    }
+
+    #[test]
+    fn exported_project_parses_back() {
+        let text = include_str!("../assets/default.ldtk");
+        let project: ldtk::LdtkJson = serde_json::from_str(text).unwrap();
+        let rules = LevelRules::default();
+        let (layout, solution) = generate_layout(
+            7,
+            &GeneratorSettings::default(),
+            &rules,
+            EXPORT_HEALTH,
+            ice_damage(&project),
+        )
+        .unwrap();
+        let level = build_level(
+            &layout,
+            &project,
+            project.next_uid,
+            "Generated_7",
+            "Generated 7",
+            solution.moves,
+        )
+        .unwrap();
+        let level_json = serde_json::to_string_pretty(&level).unwrap();
+
+        let text = insert_levels(text, &level_json)
+            .and_then(|text| replace_next_uid(&text, project.next_uid + 1))
+            .unwrap();
+        let exported: ldtk::LdtkJson = serde_json::from_str(&text).unwrap();
+        assert_eq!(exported.levels.len(), project.levels.len() + 1);
+        assert_eq!(exported.levels.last().unwrap().identifier, "Generated_7");
+        assert_eq!(exported.levels.last().unwrap().uid, project.next_uid);
+        assert_eq!(exported.next_uid, project.next_uid + 1);
+    }
+}
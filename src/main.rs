@@ -4,20 +4,47 @@ use bevy_ecs_ldtk::prelude::*;
 use bevy_kira_audio::AudioPlugin;
 
 mod components;
+mod generator;
+mod save;
 mod systems;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
 enum GameSystem {
     LoadWorld,
+    TrackLoadedLevels,
     BuildTilemap,
+    PlaceSpawnedEntities,
+    LinkSwitches,
+    TrackCollectibles,
+    OrientObstacles,
+    RestoreCheckpoint,
     ApplyPlayerAdvantage,
+    BufferPlayerInput,
     MovePlayer,
+    FollowPlayerIntoNeighbourLevel,
     ApplyPlayerVisualEffects,
     CheckForExitStates,
+    TickStatusEffects,
+    BlowWind,
+    AnimateWind,
     ApplyDamageToPlayer,
-    SpawnFallingIceOverPlayer,
+    HealPlayer,
+    ReleaseFallingIce,
     MoveFallingIce,
+    MovePushBlocks,
+    MoveEnemies,
+    CrumbleTiles,
+    LandPlayer,
+    TrackLevelStats,
+    PlayLandingEffects,
+    TelegraphEnemyMoves,
+    IndexOccupancy,
+    InteractWithWorld,
+    OperateSwitches,
+    OperateMechanisms,
+    SyncTransforms,
     FitCamera,
+    UpdateHud,
     TeardownWorld,
     _SetupMenu,
     _CloseMenu,
@@ -28,6 +55,19 @@ fn main() {
     #[cfg(target_arch = "wasm32")]
     console_error_panic_hook::set_once();
 
+    // Exporting generated levels for designers doesn't need the game itself
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some(generator::EXPORT_COMMAND) {
+        match generator::export_levels(&args[1..]) {
+            Ok(report) => println!("{}", report),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     App::new()
         .insert_resource(ClearColor(Color::rgb_u8(174, 188, 233)))
         .insert_resource(WindowDescriptor {
@@ -37,29 +77,117 @@ fn main() {
         .add_plugins(DefaultPlugins)
         .add_plugin(LdtkPlugin)
         .add_plugin(AudioPlugin)
+        .insert_resource(components::WorldLayout::default())
+        .insert_resource(components::WorldLayout::default().ldtk_settings())
         .insert_resource(LevelSelection::Index(0))
         .insert_resource(components::TileMap::default())
+        .insert_resource(components::Occupancy::default())
+        .insert_resource(components::LoadedLevels::default())
         .insert_resource(components::GameState::default())
+        .insert_resource(components::Collectibles::default())
+        .insert_resource(components::LastCheckpoint::default())
+        .insert_resource(components::ScreenShake::default())
+        .insert_resource(components::StunnedTurnTimer::default())
+        .insert_resource(components::WindStrength::default())
+        .insert_resource(components::LevelResult::default())
+        .insert_resource(components::RunStats::default())
+        .insert_resource(components::SpeedrunTimer::default())
+        .add_event::<components::PlayerLanded>()
+        .add_event::<components::WindGusted>()
+        .insert_resource(save::SaveData::load())
+        .insert_resource(components::TweenSettings::default())
         // .insert_resource(ReportExecutionOrderAmbiguities)
         .add_state(components::AppState::MainMenu)
         .register_ldtk_int_cell::<components::WallTileBundle>(1)
         .register_ldtk_int_cell::<components::ClimableTileBundle>(2)
+        .register_ldtk_int_cell::<components::CrumblingTileBundle>(3)
+        .register_ldtk_int_cell::<components::SlickTileBundle>(4)
         .register_ldtk_entity::<components::PlayerBundle>("Player")
         .register_ldtk_entity::<components::ObstacleSpikeBundle>("ObstacleSpike")
         .register_ldtk_entity::<components::ObstacleBlockBundle>("ObstacleBlock")
         .register_ldtk_entity::<components::FallingIceBundle>("FallingIce")
+        .register_ldtk_entity::<components::PressurePlateBundle>("PressurePlate")
+        .register_ldtk_entity::<components::PushBlockBundle>("PushBlock")
+        .register_ldtk_entity::<components::KeyBundle>("Key")
+        .register_ldtk_entity::<components::DoorBundle>("Door")
+        .register_ldtk_entity::<components::LeverBundle>("Lever")
+        .register_ldtk_entity::<components::GateBundle>("Gate")
+        .register_ldtk_entity::<components::GemBundle>("Gem")
+        .register_ldtk_entity::<components::CheckpointBundle>("Checkpoint")
+        .register_ldtk_entity::<components::HealthPickupBundle>("HealthPickup")
+        .register_ldtk_entity::<components::RestSpotBundle>("RestSpot")
+        .register_ldtk_entity::<components::WindZoneBundle>("WindZone")
         .register_ldtk_entity::<components::GoalBundle>("Goal")
+        .register_ldtk_entity::<components::YetiBundle>("Yeti")
         .add_startup_system(systems::setup)
         .add_system_set(
             SystemSet::on_enter(components::AppState::InGame)
                 .label(GameSystem::LoadWorld)
                 .with_system(systems::load_world),
         )
+        .add_system_to_stage(CoreStage::PreUpdate, systems::despawn_duplicate_players)
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .before(GameSystem::TrackLoadedLevels)
+                .with_system(systems::build_generated_level),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .label(GameSystem::TrackLoadedLevels)
+                .with_system(systems::track_loaded_levels),
+        )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::TrackLoadedLevels)
                 .label(GameSystem::BuildTilemap)
                 .with_system(systems::build_tilemap_with_added_tiles),
         )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::TrackLoadedLevels)
+                .label(GameSystem::PlaceSpawnedEntities)
+                .with_system(systems::place_spawned_entities_in_world),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::PlaceSpawnedEntities)
+                .label(GameSystem::BuildTilemap)
+                .with_system(systems::add_entity_tiles_to_tilemap),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::PlaceSpawnedEntities)
+                .label(GameSystem::LinkSwitches)
+                .with_system(systems::link_switches),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::PlaceSpawnedEntities)
+                .label(GameSystem::TrackCollectibles)
+                .with_system(systems::track_placed_gems),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::PlaceSpawnedEntities)
+                .label(GameSystem::OrientObstacles)
+                .with_system(systems::orient_obstacles)
+                .with_system(systems::spawn_snowflakes),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::TrackLoadedLevels)
+                .after(GameSystem::BuildTilemap)
+                .after(GameSystem::PlaceSpawnedEntities)
+                .label(GameSystem::RestoreCheckpoint)
+                .with_system(systems::restore_checkpoint),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::BuildTilemap)
+                // Before restoring the checkpoint marks it as done
+                .before(GameSystem::RestoreCheckpoint)
+                .with_system(systems::restore_crumbled_tiles),
+        )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
                 .label(GameSystem::ApplyPlayerAdvantage)
@@ -67,9 +195,23 @@ fn main() {
         )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
+                .label(GameSystem::BufferPlayerInput)
+                .with_system(systems::buffer_player_input),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::BufferPlayerInput)
+                .after(GameSystem::BuildTilemap)
+                .after(GameSystem::PlaceSpawnedEntities)
                 .label(GameSystem::MovePlayer)
                 .with_system(systems::move_player_from_input),
         )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::MovePlayer)
+                .label(GameSystem::FollowPlayerIntoNeighbourLevel)
+                .with_system(systems::follow_player_into_neighbour_level),
+        )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
                 .after(GameSystem::MovePlayer)
@@ -78,38 +220,200 @@ fn main() {
         )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::PlayLandingEffects)
                 .label(GameSystem::FitCamera)
                 .with_system(systems::fit_camera_inside_current_level),
         )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::InteractWithWorld)
+                .label(GameSystem::UpdateHud)
+                .with_system(systems::update_hud)
+                .with_system(systems::toggle_speedrun_timer)
+                .with_system(systems::update_speedrun_timer),
+        )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
                 .with_run_criteria(systems::run_if_world_should_update)
                 .after(GameSystem::MovePlayer)
+                .label(GameSystem::TickStatusEffects)
+                .with_system(systems::tick_status_effects),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .with_run_criteria(systems::run_if_world_should_update)
+                .after(GameSystem::MovePlayer)
+                .after(GameSystem::TickStatusEffects)
+                .label(GameSystem::BlowWind)
+                .with_system(systems::blow_wind),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::BlowWind)
+                .label(GameSystem::AnimateWind)
+                .with_system(systems::play_wind_ambience)
+                .with_system(systems::drift_snowflakes),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .with_run_criteria(systems::run_if_world_should_update)
+                .after(GameSystem::MovePlayer)
+                .after(GameSystem::TickStatusEffects)
                 .label(GameSystem::MoveFallingIce)
                 .with_system(systems::move_falling_ice),
         )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
                 .with_run_criteria(systems::run_if_world_should_update)
+                .after(GameSystem::MovePlayer)
                 .after(GameSystem::MoveFallingIce)
+                .label(GameSystem::MovePushBlocks)
+                .with_system(systems::move_push_blocks),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .with_run_criteria(systems::run_if_world_should_update)
+                .after(GameSystem::PlaceSpawnedEntities)
+                .after(GameSystem::MovePlayer)
+                .after(GameSystem::MoveFallingIce)
+                .after(GameSystem::MovePushBlocks)
+                .label(GameSystem::MoveEnemies)
+                .with_system(systems::move_enemies),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .with_run_criteria(systems::run_if_world_should_update)
+                .after(GameSystem::MoveEnemies)
+                .label(GameSystem::CrumbleTiles)
+                .with_system(systems::crumble_tiles)
+                .with_system(systems::melt_debris),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::MovePlayer)
+                .after(GameSystem::MovePushBlocks)
+                .after(GameSystem::CrumbleTiles)
+                .after(GameSystem::ApplyDamageToPlayer)
+                .after(GameSystem::BlowWind)
+                .label(GameSystem::LandPlayer)
+                .with_system(systems::land_player),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::LandPlayer)
+                .label(GameSystem::PlayLandingEffects)
+                .with_system(systems::play_landing_sfx)
+                .with_system(systems::shake_screen_on_landing),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::PlaceSpawnedEntities)
+                .after(GameSystem::MovePlayer)
+                .after(GameSystem::MoveFallingIce)
+                .after(GameSystem::MovePushBlocks)
+                .after(GameSystem::MoveEnemies)
+                .after(GameSystem::CrumbleTiles)
+                .after(GameSystem::BlowWind)
+                .label(GameSystem::IndexOccupancy)
+                .with_system(systems::index_occupancy),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::IndexOccupancy)
+                .after(GameSystem::LinkSwitches)
+                .after(GameSystem::TrackCollectibles)
+                .after(GameSystem::RestoreCheckpoint)
+                .label(GameSystem::InteractWithWorld)
+                .with_system(systems::collect_keys)
+                .with_system(systems::collect_gems)
+                .with_system(systems::light_checkpoints)
+                .with_system(systems::collect_health_pickups)
+                .with_system(systems::pull_levers)
+                .with_system(systems::press_pressure_plates),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::InteractWithWorld)
+                .label(GameSystem::OperateSwitches)
+                .with_system(systems::operate_switches),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::OperateSwitches)
+                .label(GameSystem::OperateMechanisms)
+                .with_system(systems::open_gates)
+                .with_system(systems::retract_spikes),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .with_run_criteria(systems::run_if_world_should_update)
+                .after(GameSystem::IndexOccupancy)
+                .after(GameSystem::OperateMechanisms)
                 .label(GameSystem::ApplyDamageToPlayer)
                 .with_system(systems::apply_damage_to_player),
         )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
                 .with_run_criteria(systems::run_if_world_should_update)
-                .after(GameSystem::MoveFallingIce)
-                .label(GameSystem::SpawnFallingIceOverPlayer)
-                .with_system(systems::spawn_falling_ice_over_player), // spawn ice now for the next turn
+                .after(GameSystem::ApplyDamageToPlayer)
+                .label(GameSystem::HealPlayer)
+                .with_system(systems::heal_player),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .with_run_criteria(systems::run_if_world_should_update)
+                .after(GameSystem::IndexOccupancy)
+                .after(GameSystem::OperateSwitches)
+                .label(GameSystem::ReleaseFallingIce)
+                .with_system(systems::release_falling_ice), // release ice now for the next turn
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::IndexOccupancy)
+                .after(GameSystem::ApplyDamageToPlayer)
+                .label(GameSystem::TelegraphEnemyMoves)
+                .with_system(systems::spawn_enemy_telegraphs)
+                .with_system(systems::telegraph_enemy_moves),
         )
         .add_system_set(
             SystemSet::on_update(components::AppState::InGame)
                 .after(GameSystem::MovePlayer)
+                .after(GameSystem::MoveFallingIce)
+                .after(GameSystem::MovePushBlocks)
+                .after(GameSystem::MoveEnemies)
+                .after(GameSystem::CrumbleTiles)
+                .after(GameSystem::ApplyDamageToPlayer)
+                .after(GameSystem::PlaceSpawnedEntities)
+                .after(GameSystem::TelegraphEnemyMoves)
+                .label(GameSystem::SyncTransforms)
+                .with_system(systems::animate_tweens)
+                .with_system(systems::snap_transforms_to_grid)
+                .with_system(systems::animate_ice_shards)
+                .with_system(systems::shake_warned_ice),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::ApplyDamageToPlayer)
+                .after(GameSystem::HealPlayer)
+                .after(GameSystem::LandPlayer)
+                .label(GameSystem::TrackLevelStats)
+                .with_system(systems::track_damage_taken)
+                .with_system(systems::tick_level_timer),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::InGame)
+                .after(GameSystem::IndexOccupancy)
+                .after(GameSystem::FollowPlayerIntoNeighbourLevel)
+                .after(GameSystem::HealPlayer)
+                .after(GameSystem::LandPlayer)
+                .after(GameSystem::TrackLevelStats)
                 .label(GameSystem::CheckForExitStates)
                 .with_system(systems::check_for_player_death)
                 .with_system(systems::check_player_reached_goal)
+                .with_system(systems::check_move_limit)
                 .with_system(systems::exit_on_esc),
         )
+        .add_system_to_stage(CoreStage::PostUpdate, systems::prune_occupancy)
         .add_system_set(
             SystemSet::on_exit(components::AppState::InGame)
                 .label(GameSystem::TeardownWorld)
@@ -125,11 +429,27 @@ fn main() {
         .add_system_set(
             SystemSet::on_exit(components::AppState::MainMenu).with_system(systems::close_menu),
         )
+        .add_system_set(
+            SystemSet::on_enter(components::AppState::LevelResults)
+                .with_system(systems::setup_level_results),
+        )
+        .add_system_set(
+            SystemSet::on_update(components::AppState::LevelResults)
+                .with_system(systems::continue_from_results)
+                // The clock keeps running while the results are up
+                .with_system(systems::update_speedrun_timer),
+        )
+        .add_system_set(
+            SystemSet::on_exit(components::AppState::LevelResults)
+                .with_system(systems::close_level_results),
+        )
         .add_system_set(
             SystemSet::on_enter(components::AppState::Credits).with_system(systems::setup_credits),
         )
         .add_system_set(
-            SystemSet::on_update(components::AppState::Credits).with_system(systems::exit_on_esc),
+            SystemSet::on_update(components::AppState::Credits)
+                .with_system(systems::exit_on_esc)
+                .with_system(systems::roll_credits),
         )
         .add_system_set(
             SystemSet::on_exit(components::AppState::Credits).with_system(systems::close_credits),
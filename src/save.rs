@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// Progress kept between runs in a plain text file next to the game, with lines per
+// level for the most gems ever collected in it and its best finish, and the best
+// finish of each day's daily challenge:
+//
+//     level 1: 2/3 collected
+//     level 1: 14 moves, 3 stars
+//     daily 2022-03-05: 31 moves, 2 stars
+//
+// Speedrun splits go in a file of their own, written whenever a run is finished,
+// for the last finished run and the best one, with the time in seconds and
+// turns taken from the start of the run to the end of each level:
+//
+//     last level 1: 12.34 seconds, 30 turns
+//     best level 1: 11.02 seconds, 28 turns
+//
+// The web build has nowhere to write to, so there it only lasts until the page
+// is closed.
+const SAVE_FILE: &str = "save.txt";
+const SPLITS_FILE: &str = "splits.txt";
+
+// How far into the run a level was finished
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Split {
+    // In seconds
+    pub time: f64,
+    pub turns: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SaveData {
+    // Level index to (collected, total)
+    pub gems: HashMap<usize, (usize, usize)>,
+    // Level index to (moves, stars), stars only for levels with a par
+    pub results: HashMap<usize, (u32, Option<u32>)>,
+    // Date of the daily challenge to (moves, stars)
+    pub daily_results: BTreeMap<String, (u32, Option<u32>)>,
+    // One per level, in order
+    pub last_splits: Vec<Split>,
+    pub best_splits: Vec<Split>,
+}
+
+impl SaveData {
+    pub fn load() -> Self {
+        let mut save_data = SaveData::default();
+        for line in read_file(SAVE_FILE).unwrap_or_default().lines() {
+            if let Some((index, collected, total)) = parse_gems(line) {
+                save_data.gems.insert(index, (collected, total));
+            } else if let Some((index, moves, stars)) = parse_result(line) {
+                save_data.results.insert(index, (moves, stars));
+            } else if let Some((date, moves, stars)) = parse_daily_result(line) {
+                save_data.daily_results.insert(date, (moves, stars));
+            }
+        }
+        for line in read_file(SPLITS_FILE).unwrap_or_default().lines() {
+            // Splits only count in order, a gap in them throws out the rest
+            if let Some((index, split)) = line.strip_prefix("last ").and_then(parse_split) {
+                if index == save_data.last_splits.len() {
+                    save_data.last_splits.push(split);
+                }
+            } else if let Some((index, split)) = line.strip_prefix("best ").and_then(parse_split) {
+                if index == save_data.best_splits.len() {
+                    save_data.best_splits.push(split);
+                }
+            }
+        }
+        save_data
+    }
+
+    pub fn save(&self) {
+        let levels: BTreeSet<_> = self.gems.keys().chain(self.results.keys()).collect();
+
+        let mut contents = String::new();
+        for index in levels {
+            // Levels are numbered from 1 for whoever reads the file
+            if let Some((collected, total)) = self.gems.get(index) {
+                contents += &format!("level {}: {}/{} collected\n", index + 1, collected, total);
+            }
+            if let Some((moves, stars)) = self.results.get(index) {
+                contents += &format!("level {}: {}\n", index + 1, format_result(*moves, *stars));
+            }
+        }
+        for (date, (moves, stars)) in self.daily_results.iter() {
+            contents += &format!("daily {}: {}\n", date, format_result(*moves, *stars));
+        }
+
+        write_file(SAVE_FILE, &contents);
+    }
+
+    fn save_splits(&self) {
+        let mut splits = String::new();
+        for (kind, kind_splits) in [("last", &self.last_splits), ("best", &self.best_splits)] {
+            for (index, split) in kind_splits.iter().enumerate() {
+                splits += &format!(
+                    "{} level {}: {:.2} seconds, {} turns\n",
+                    kind,
+                    index + 1,
+                    split.time,
+                    split.turns
+                );
+            }
+        }
+        write_file(SPLITS_FILE, &splits);
+    }
+
+    pub fn best_gems(&self, index: usize) -> Option<(usize, usize)> {
+        self.gems.get(&index).copied()
+    }
+
+    // Returns whether this beat the best so far
+    pub fn record_gems(&mut self, index: usize, collected: usize, total: usize) -> bool {
+        match self.gems.get(&index) {
+            Some((best, _)) if *best >= collected => false,
+            _ => {
+                self.gems.insert(index, (collected, total));
+                true
+            }
+        }
+    }
+
+    pub fn best_result(&self, index: usize) -> Option<(u32, Option<u32>)> {
+        self.results.get(&index).copied()
+    }
+
+    // Returns whether this beat the best so far
+    pub fn record_result(&mut self, index: usize, moves: u32, stars: Option<u32>) -> bool {
+        match self.results.get(&index) {
+            Some(best) if !is_better_result((moves, stars), *best) => false,
+            _ => {
+                self.results.insert(index, (moves, stars));
+                true
+            }
+        }
+    }
+
+    pub fn best_daily_result(&self, date: &str) -> Option<(u32, Option<u32>)> {
+        self.daily_results.get(date).copied()
+    }
+
+    // Returns whether this beat the best so far that day
+    pub fn record_daily_result(&mut self, date: &str, moves: u32, stars: Option<u32>) -> bool {
+        match self.daily_results.get(date) {
+            Some(best) if !is_better_result((moves, stars), *best) => false,
+            _ => {
+                self.daily_results.insert(date.to_string(), (moves, stars));
+                true
+            }
+        }
+    }
+
+    // A finished run's splits, which replace the best ones if it was faster.
+    // Returns whether they did.
+    pub fn record_splits(&mut self, splits: &[Split]) -> bool {
+        self.last_splits = splits.to_vec();
+        let is_best = match (self.best_splits.last(), splits.last()) {
+            (Some(best), Some(split)) if best.time <= split.time => false,
+            _ => {
+                self.best_splits = splits.to_vec();
+                true
+            }
+        };
+        self.save_splits();
+        is_best
+    }
+}
+
+fn parse_gems(line: &str) -> Option<(usize, usize, usize)> {
+    let (level, gems) = line.strip_prefix("level ")?.split_once(':')?;
+    let (collected, total) = gems
+        .trim()
+        .strip_suffix("collected")?
+        .trim()
+        .split_once('/')?;
+    Some((
+        level.trim().parse::<usize>().ok()?.checked_sub(1)?,
+        collected.parse().ok()?,
+        total.parse().ok()?,
+    ))
+}
+
+// More stars is better, then fewer moves
+fn is_better_result(
+    (moves, stars): (u32, Option<u32>),
+    (best_moves, best_stars): (u32, Option<u32>),
+) -> bool {
+    (stars, std::cmp::Reverse(moves)) > (best_stars, std::cmp::Reverse(best_moves))
+}
+
+fn format_result(moves: u32, stars: Option<u32>) -> String {
+    match stars {
+        Some(stars) => format!("{} moves, {} stars", moves, stars),
+        None => format!("{} moves", moves),
+    }
+}
+
+fn parse_result(line: &str) -> Option<(usize, u32, Option<u32>)> {
+    let (level, result) = line.strip_prefix("level ")?.split_once(':')?;
+    let (moves, stars) = parse_moves(result)?;
+    Some((
+        level.trim().parse::<usize>().ok()?.checked_sub(1)?,
+        moves,
+        stars,
+    ))
+}
+
+fn parse_daily_result(line: &str) -> Option<(String, u32, Option<u32>)> {
+    let (date, result) = line.strip_prefix("daily ")?.split_once(':')?;
+    let (moves, stars) = parse_moves(result)?;
+    Some((date.trim().to_string(), moves, stars))
+}
+
+fn parse_moves(result: &str) -> Option<(u32, Option<u32>)> {
+    let (moves, stars) = match result.split_once(',') {
+        Some((moves, stars)) => (
+            moves,
+            Some(stars.trim().strip_suffix("stars")?.trim().parse().ok()?),
+        ),
+        None => (result, None),
+    };
+    Some((
+        moves.trim().strip_suffix("moves")?.trim().parse().ok()?,
+        stars,
+    ))
+}
+
+fn parse_split(line: &str) -> Option<(usize, Split)> {
+    let (level, split) = line.strip_prefix("level ")?.split_once(':')?;
+    let (time, turns) = split.split_once(',')?;
+    Some((
+        level.trim().parse::<usize>().ok()?.checked_sub(1)?,
+        Split {
+            time: time.trim().strip_suffix("seconds")?.trim().parse().ok()?,
+            turns: turns.trim().strip_suffix("turns")?.trim().parse().ok()?,
+        },
+    ))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_file(file: &str) -> Option<String> {
+    std::fs::read_to_string(file).ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_file(_: &str) -> Option<String> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_file(file: &str, contents: &str) {
+    if let Err(e) = std::fs::write(file, contents) {
+        eprintln!("could not write {}: {}", file, e);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_file(_: &str, _: &str) {}
@@ -1,21 +1,90 @@
 use crate::components::*;
+use crate::generator;
+use crate::save::{SaveData, Split};
 use bevy::ecs::schedule::*;
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
-use bevy_kira_audio::Audio;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_kira_audio::{Audio, AudioChannel};
 use rand::Rng;
 
+use std::collections::{HashMap, HashSet};
+
 const SPEED_BUTTON_LABEL: &str = "SPEED";
 const STRENGTH_BUTTON_LABEL: &str = "STRENGTH";
 const HEALTH_BUTTON_LABEL: &str = "HEALTH";
+const CONTINUE_BUTTON_LABEL: &str = "CONTINUE";
+const DAILY_CHALLENGE_BUTTON_LABEL: &str = "DAILY CHALLENGE";
+const RANDOM_CLIMB_BUTTON_LABEL: &str = "RANDOM CLIMB";
+const SEPARATE_LAYOUT_BUTTON_LABEL: &str = "LEVELS: ONE AT A TIME";
+const CONNECTED_LAYOUT_BUTTON_LABEL: &str = "LEVELS: ONE MOUNTAIN";
+const HEALTH_ADVANTAGE_HEALTH: i32 = 200;
+// Generated levels are checked to be climbable with this much health, and it's the same
+// for everyone so daily challenge results can be compared
+const GENERATED_LEVEL_ADVANTAGE: Advantage = Advantage::Health;
+const GENERATED_LEVEL_HEALTH: i32 = HEALTH_ADVANTAGE_HEALTH;
+// Random climbs go higher than the daily challenge, and are more dangerous
+const RANDOM_CLIMB_SETTINGS: generator::GeneratorSettings = generator::GeneratorSettings {
+    width: 16,
+    height: 24,
+    hazard_density: 0.4,
+    ladder_ratio: 0.1,
+};
 const NUM_LEVELS: usize = 4;
+const BLEEDING_DAMAGE_PER_TICK: i32 = 5;
+const AMBIENCE_CHANNEL: &str = "ambience";
+const AMBIENCE_CALM_VOLUME: f32 = 0.6;
+// Gems borrow the goal's jingle, played quicker and quieter so it's not mistaken for it
+const GEM_CHANNEL: &str = "gems";
+const GEM_PLAYBACK_RATE: f32 = 1.5;
+const GEM_VOLUME: f32 = 0.5;
+// Shattering is the falling ice's own sound, cut short and higher, rather than a swing
+const ICE_SHATTER_CHANNEL: &str = "ice_shatter";
+const ICE_SHATTER_PLAYBACK_RATE: f32 = 1.8;
+const GUST_FADE_DURATION: f32 = 1.5;
+const SNOWFLAKES_PER_TILE: i32 = 2;
+const SNOWFLAKE_FALL_SPEED: f32 = 12.0;
+const SNOWFLAKE_GUST_SPEEDUP: f32 = 3.0;
+// In pixels per second
+const CREDITS_ROLL_SPEED: f32 = 40.0;
+const SPLIT_AHEAD_COLOR: Color = Color::rgb(1.0, 0.84, 0.35);
+const SPLIT_BEHIND_COLOR: Color = Color::rgb(0.9, 0.3, 0.27);
+// Built in so the web build has them too
+const CREDITS: &str = include_str!("../assets/credits.txt");
 
 pub fn setup(asset_server: Res<AssetServer>, audio: Res<Audio>) {
     asset_server.watch_for_changes().unwrap();
-    audio.play_looped(asset_server.load("audio/AMB_PolarWind_Loop.ogg"));
+    // The wind gets its own channel, so gusts can turn it up
+    let ambience_channel = AudioChannel::new(AMBIENCE_CHANNEL.to_owned());
+    audio.play_looped_in_channel(
+        asset_server.load("audio/AMB_PolarWind_Loop.ogg"),
+        &ambience_channel,
+    );
+    audio.set_volume_in_channel(AMBIENCE_CALM_VOLUME, &ambience_channel);
+
+    let gem_channel = AudioChannel::new(GEM_CHANNEL.to_owned());
+    audio.set_playback_rate_in_channel(GEM_PLAYBACK_RATE, &gem_channel);
+    audio.set_volume_in_channel(GEM_VOLUME, &gem_channel);
+
+    audio.set_playback_rate_in_channel(
+        ICE_SHATTER_PLAYBACK_RATE,
+        &AudioChannel::new(ICE_SHATTER_CHANNEL.to_owned()),
+    );
+}
+
+fn world_layout_button_label(world_layout: WorldLayout) -> &'static str {
+    match world_layout {
+        WorldLayout::Separate => SEPARATE_LAYOUT_BUTTON_LABEL,
+        WorldLayout::Connected => CONNECTED_LAYOUT_BUTTON_LABEL,
+    }
 }
 
-pub fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn setup_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    world_layout: Res<WorldLayout>,
+) {
     commands.spawn_bundle(UiCameraBundle::default());
     commands.spawn_bundle(NodeBundle {
         style: Style {
@@ -74,15 +143,18 @@ pub fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
                 ..Default::default()
             });
 
-            for label in vec![
-                SPEED_BUTTON_LABEL,
-                STRENGTH_BUTTON_LABEL,
-                HEALTH_BUTTON_LABEL,
+            for (label, width) in [
+                (SPEED_BUTTON_LABEL, 250.0),
+                (STRENGTH_BUTTON_LABEL, 250.0),
+                (HEALTH_BUTTON_LABEL, 250.0),
+                (DAILY_CHALLENGE_BUTTON_LABEL, 400.0),
+                (RANDOM_CLIMB_BUTTON_LABEL, 400.0),
+                (world_layout_button_label(*world_layout), 550.0),
             ] {
                 parent
                     .spawn_bundle(ButtonBundle {
                         style: Style {
-                            size: Size::new(Val::Px(250.0), Val::Px(65.0)),
+                            size: Size::new(Val::Px(width), Val::Px(65.0)),
                             margin: Rect::all(Val::Auto),
                             justify_content: JustifyContent::Center,
                             align_items: AlignItems::Center,
@@ -122,7 +194,49 @@ pub fn close_menu(mut commands: Commands, entity_query: Query<Entity>) {
     commands.remove_resource::<UiSounds>();
 }
 
-pub fn setup_credits(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn setup_credits(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    collectibles: Res<Collectibles>,
+    run_stats: Res<RunStats>,
+) {
+    let font = asset_server.load("fonts/Minecraft.ttf");
+    let stat_color = Color::rgb(0.9, 0.9, 0.9);
+    let (gems_collected, gems_placed) = collectibles.in_run();
+
+    let advantage = match run_stats.advantage {
+        Some(Advantage::Speed) => SPEED_BUTTON_LABEL,
+        Some(Advantage::Strength) => STRENGTH_BUTTON_LABEL,
+        Some(Advantage::Health) => HEALTH_BUTTON_LABEL,
+        None => "NONE",
+    };
+    let minutes = run_stats.time as u32 / 60;
+    let seconds = run_stats.time as u32 % 60;
+
+    let stats = [
+        (format!("ADVANTAGE: {}", advantage), stat_color),
+        (
+            format!("GEMS COLLECTED: {}/{}", gems_collected, gems_placed),
+            Color::rgb_u8(120, 220, 255),
+        ),
+        (format!("MOVES: {}", run_stats.moves), stat_color),
+        (format!("DEATHS: {}", run_stats.deaths), stat_color),
+        (
+            format!("DAMAGE DEALT: {}", run_stats.damage_dealt),
+            stat_color,
+        ),
+        (
+            format!("DAMAGE TAKEN: {}", run_stats.damage_taken),
+            stat_color,
+        ),
+        (format!("ICE DODGED: {}", run_stats.ice_dodged), stat_color),
+        (
+            format!("OBSTACLES DESTROYED: {}", run_stats.obstacles_destroyed),
+            stat_color,
+        ),
+        (format!("TIME: {}:{:02}", minutes, seconds), stat_color),
+    ];
+
     commands.spawn_bundle(UiCameraBundle::default());
     commands.spawn_bundle(NodeBundle {
         style: Style {
@@ -136,53 +250,172 @@ pub fn setup_credits(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
-                size: Size::new(Val::Percent(100.0), Val::Percent(75.0)),
-                align_self: AlignSelf::Center,
-                align_items: AlignItems::Center,
-                justify_content: JustifyContent::SpaceEvenly,
-                flex_direction: FlexDirection::ColumnReverse,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
                 ..Default::default()
             },
             color: Color::NONE.into(),
             ..Default::default()
         })
         .with_children(|parent| {
-            parent.spawn_bundle(TextBundle {
-                style: Style {
-                    margin: Rect::all(Val::Px(50.0)),
-                    ..Default::default()
-                },
-                text: Text::with_section(
-                    "YOU REACHED THE TOP!",
-                    TextStyle {
-                        font: asset_server.load("fonts/Minecraft.ttf"),
-                        font_size: 62.0,
-                        color: Color::rgb_u8(234, 237, 194),
+            // How the run went on the left
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(60.0), Val::Percent(100.0)),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        flex_direction: FlexDirection::ColumnReverse,
+                        ..Default::default()
                     },
-                    Default::default(),
-                ),
-                ..Default::default()
-            });
-
-            parent.spawn_bundle(TextBundle {
-                style: Style {
-                    margin: Rect::all(Val::Px(50.0)),
+                    color: Color::NONE.into(),
                     ..Default::default()
-                },
-                text: Text::with_section(
-                    "PRESS ESC TO RETURN TO THE MAIN MENU",
-                    TextStyle {
-                        font: asset_server.load("fonts/Minecraft.ttf"),
-                        font_size: 42.0,
-                        color: Color::rgb(0.9, 0.9, 0.9),
+                })
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle {
+                        style: Style {
+                            margin: Rect::all(Val::Px(30.0)),
+                            ..Default::default()
+                        },
+                        text: Text::with_section(
+                            "YOU REACHED THE TOP!",
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 62.0,
+                                color: Color::rgb_u8(234, 237, 194),
+                            },
+                            Default::default(),
+                        ),
+                        ..Default::default()
+                    });
+
+                    for (line, color) in stats {
+                        parent.spawn_bundle(TextBundle {
+                            style: Style {
+                                margin: Rect::all(Val::Px(6.0)),
+                                ..Default::default()
+                            },
+                            text: Text::with_section(
+                                line,
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 32.0,
+                                    color,
+                                },
+                                Default::default(),
+                            ),
+                            ..Default::default()
+                        });
+                    }
+
+                    parent.spawn_bundle(TextBundle {
+                        style: Style {
+                            margin: Rect::all(Val::Px(30.0)),
+                            ..Default::default()
+                        },
+                        text: Text::with_section(
+                            "PRESS ESC TO RETURN TO THE MAIN MENU",
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 32.0,
+                                color: stat_color,
+                            },
+                            Default::default(),
+                        ),
+                        ..Default::default()
+                    });
+                });
+
+            // Who made it on the right, rolling up from below
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(40.0), Val::Percent(100.0)),
+                        overflow: Overflow::Hidden,
+                        ..Default::default()
                     },
-                    Default::default(),
-                ),
-                ..Default::default()
-            });
+                    color: Color::NONE.into(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(100.0), Val::Auto),
+                                position_type: PositionType::Absolute,
+                                position: Rect {
+                                    top: Val::Percent(100.0),
+                                    ..Default::default()
+                                },
+                                align_items: AlignItems::Center,
+                                flex_direction: FlexDirection::ColumnReverse,
+                                ..Default::default()
+                            },
+                            color: Color::NONE.into(),
+                            ..Default::default()
+                        })
+                        .insert(RollingCredits::default())
+                        .with_children(|parent| {
+                            for line in CREDITS.lines().filter(|line| !line.starts_with('#')) {
+                                let line = line.trim();
+                                if line.is_empty() {
+                                    parent.spawn_bundle(NodeBundle {
+                                        style: Style {
+                                            size: Size::new(Val::Auto, Val::Px(32.0)),
+                                            ..Default::default()
+                                        },
+                                        color: Color::NONE.into(),
+                                        ..Default::default()
+                                    });
+                                    continue;
+                                }
+
+                                let (line, font_size, color) = match line.strip_prefix("== ") {
+                                    Some(heading) => (heading, 36.0, Color::rgb_u8(234, 237, 194)),
+                                    None => (line, 28.0, stat_color),
+                                };
+                                parent.spawn_bundle(TextBundle {
+                                    style: Style {
+                                        margin: Rect::all(Val::Px(6.0)),
+                                        ..Default::default()
+                                    },
+                                    text: Text::with_section(
+                                        line,
+                                        TextStyle {
+                                            font: font.clone(),
+                                            font_size,
+                                            color,
+                                        },
+                                        Default::default(),
+                                    ),
+                                    ..Default::default()
+                                });
+                            }
+                        });
+                });
         });
 }
 
+// Scrolls the credits up through their panel, starting them over from the bottom once they're gone
+pub fn roll_credits(
+    time: Res<Time>,
+    mut credits_query: Query<(&mut RollingCredits, &mut Style, &Node, &Parent)>,
+    panel_query: Query<&Node, Without<RollingCredits>>,
+) {
+    for (mut credits, mut style, node, parent) in credits_query.iter_mut() {
+        let panel_height = match panel_query.get(parent.0) {
+            Ok(panel) => panel.size.y,
+            Err(_) => continue,
+        };
+
+        credits.scrolled += CREDITS_ROLL_SPEED * time.delta_seconds();
+        if credits.scrolled > panel_height + node.size.y {
+            credits.scrolled = 0.0;
+        }
+        style.position.top = Val::Px(panel_height - credits.scrolled);
+    }
+}
+
 pub fn close_credits(mut commands: Commands, entity_query: Query<Entity>) {
     for e in entity_query.iter() {
         commands.entity(e).despawn();
@@ -196,6 +429,8 @@ pub fn handle_ui_buttons(
     >,
     mut app_state: ResMut<State<AppState>>,
     mut game_state: ResMut<GameState>,
+    mut world_layout: ResMut<WorldLayout>,
+    mut ldtk_settings: ResMut<LdtkSettings>,
     mut text_query: Query<&mut Text>,
     ui_sounds: Res<UiSounds>,
     audio: Res<Audio>,
@@ -206,7 +441,8 @@ pub fn handle_ui_buttons(
             Interaction::Clicked => {
                 audio.play(ui_sounds.button_clicked_sfx.clone());
 
-                match text.sections[0].value.as_str() {
+                let label = text.sections[0].value.clone();
+                match label.as_str() {
                     SPEED_BUTTON_LABEL => {
                         game_state.player_advantage = Some(Advantage::Speed);
                     }
@@ -216,6 +452,26 @@ pub fn handle_ui_buttons(
                     HEALTH_BUTTON_LABEL => {
                         game_state.player_advantage = Some(Advantage::Health);
                     }
+                    DAILY_CHALLENGE_BUTTON_LABEL => {
+                        game_state.player_advantage = Some(GENERATED_LEVEL_ADVANTAGE);
+                        game_state.generated_level =
+                            Some(GeneratedLevel::Daily(DailyChallenge::today()));
+                    }
+                    RANDOM_CLIMB_BUTTON_LABEL => {
+                        game_state.player_advantage = Some(GENERATED_LEVEL_ADVANTAGE);
+                        game_state.generated_level = Some(GeneratedLevel::Random {
+                            seed: rand::random::<u32>() as u64,
+                            settings: RANDOM_CLIMB_SETTINGS,
+                        });
+                    }
+                    // Switches how the levels are put together, without starting yet
+                    SEPARATE_LAYOUT_BUTTON_LABEL | CONNECTED_LAYOUT_BUTTON_LABEL => {
+                        *world_layout = world_layout.toggled();
+                        *ldtk_settings = world_layout.ldtk_settings();
+                        text.sections[0].value =
+                            world_layout_button_label(*world_layout).to_string();
+                        continue;
+                    }
                     _ => panic!("unknown button"),
                 }
 
@@ -233,21 +489,87 @@ pub fn handle_ui_buttons(
     }
 }
 
-const TILE_SIZE: i32 = 64;
-const WORLD_SIZE: i32 = 16;
-
 pub fn load_world(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut level_selection: ResMut<LevelSelection>,
+    mut loaded_levels: ResMut<LoadedLevels>,
+    mut collectibles: ResMut<Collectibles>,
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+    mut run_stats: ResMut<RunStats>,
+    mut speedrun_timer: ResMut<SpeedrunTimer>,
+    game_state: Res<GameState>,
+    time: Res<Time>,
 ) {
     *level_selection = LevelSelection::Index(0);
-
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
-    commands.spawn_bundle(LdtkWorldBundle {
-        ldtk_handle: asset_server.load("default_baked.ldtk"),
+    *loaded_levels = LoadedLevels::default();
+    *collectibles = Collectibles::default();
+    *last_checkpoint = LastCheckpoint::default();
+    *run_stats = RunStats {
+        advantage: game_state.player_advantage,
+        started_at: time.seconds_since_startup(),
         ..Default::default()
-    });
+    };
+    speedrun_timer.splits.clear();
+
+    commands
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(GameCamera);
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(16.0),
+                    left: Val::Px(16.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/Minecraft.ttf"),
+                    font_size: 32.0,
+                    color: Color::rgb_u8(234, 237, 194),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(HudText);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(16.0),
+                    right: Val::Px(16.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                alignment: TextAlignment {
+                    horizontal: HorizontalAlign::Right,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(SpeedrunTimerText);
+    let ldtk_handle = asset_server.load("default_baked.ldtk");
+    if game_state.generated_level.is_some() {
+        commands.insert_resource(PendingGeneratedLevel(ldtk_handle));
+    } else {
+        commands.remove_resource::<PendingGeneratedLevel>();
+        commands.spawn_bundle(LdtkWorldBundle {
+            ldtk_handle,
+            ..Default::default()
+        });
+    }
     // preload audio
     commands.insert_resource(GameSounds {
         player_movement_sfxs: vec![
@@ -269,6 +591,8 @@ pub fn load_world(
         ],
         player_attack_sfx: asset_server.load("audio/SFX_Attack.ogg"),
         falling_ice_sfx: asset_server.load("audio/SFX_FallingIce.ogg"),
+        ice_shatter_sfx: asset_server.load("audio/SFX_FallingIce.ogg"),
+        gem_sfx: asset_server.load("audio/SFX_Goal.ogg"),
         goal_sfx: asset_server.load("audio/SFX_Goal.ogg"),
     });
     // preload textures
@@ -287,6 +611,78 @@ pub fn load_world(
     });
 }
 
+// A generated level is dressed up as a level of the hand-made project once that's loaded
+pub fn build_generated_level(
+    mut commands: Commands,
+    pending_generated_level: Option<Res<PendingGeneratedLevel>>,
+    mut game_state: ResMut<GameState>,
+    mut app_state: ResMut<State<AppState>>,
+    mut ldtk_assets: ResMut<Assets<LdtkAsset>>,
+    mut ldtk_levels: ResMut<Assets<LdtkLevel>>,
+) {
+    let (pending_generated_level, generated_level) =
+        match (pending_generated_level, game_state.generated_level) {
+            (Some(pending_generated_level), Some(generated_level)) => {
+                (pending_generated_level, generated_level)
+            }
+            _ => return,
+        };
+    let ldtk_asset = match ldtk_assets.get(&pending_generated_level.0) {
+        Some(ldtk_asset) => ldtk_asset,
+        None => return,
+    };
+    commands.remove_resource::<PendingGeneratedLevel>();
+
+    // The generated level keeps the rules of the level it's dressed up as
+    let project = &ldtk_asset.project;
+    let rules = project
+        .levels
+        .first()
+        .map(LevelRules::from_level)
+        .unwrap_or_default();
+    let ice_damage = generator::ice_damage(project);
+    let uid = project.next_uid;
+    let level = generator::generate_layout(
+        generated_level.seed(),
+        &generated_level.settings(),
+        &rules,
+        GENERATED_LEVEL_HEALTH,
+        ice_damage,
+    )
+    .and_then(|(layout, solution)| {
+        generator::build_level(
+            &layout,
+            project,
+            uid,
+            "Generated_Level",
+            &generated_level.name(),
+            solution.moves,
+        )
+    });
+    let level = match level {
+        Some(level) => level,
+        None => {
+            // Nothing was ever loaded, so there's nothing else to clear on the way out
+            *game_state = GameState::default();
+            app_state.overwrite_set(AppState::MainMenu).unwrap();
+            return;
+        }
+    };
+
+    let mut generated_project = project.clone();
+    generated_project.levels = vec![level.clone()];
+    let generated_asset = LdtkAsset {
+        project: generated_project,
+        tileset_map: ldtk_asset.tileset_map.clone(),
+        level_map: HashMap::from([(uid, ldtk_levels.add(LdtkLevel { level }))]),
+    };
+
+    commands.spawn_bundle(LdtkWorldBundle {
+        ldtk_handle: ldtk_assets.add(generated_asset),
+        ..Default::default()
+    });
+}
+
 pub fn teardown_world(mut commands: Commands, entity_query: Query<Entity>) {
     for e in entity_query.iter() {
         commands.entity(e).despawn();
@@ -298,501 +694,3006 @@ pub fn teardown_world(mut commands: Commands, entity_query: Query<Entity>) {
 }
 
 pub fn apply_player_advantage_on_player_added(
-    mut player_query: Query<(&mut Speed, &mut Damage, &mut Health), (With<Player>, Added<Player>)>,
+    mut player_query: Query<
+        (
+            &mut Speed,
+            &mut Damage,
+            &mut Health,
+            &mut MaxHealth,
+            &mut Attack,
+        ),
+        (With<Player>, Added<Player>),
+    >,
     game_state: Res<GameState>,
 ) {
-    if let Ok((mut speed, mut damage, mut health)) = player_query.get_single_mut() {
+    if let Ok((mut speed, mut damage, mut health, mut max_health, mut attack)) =
+        player_query.get_single_mut()
+    {
         match game_state.player_advantage {
             Some(Advantage::Speed) => speed.0 = 2,
-            Some(Advantage::Strength) => damage.0 = 100,
-            Some(Advantage::Health) => health.0 = 200,
+            Some(Advantage::Strength) => {
+                damage.0 = 100;
+                attack.damage = damage.0;
+                attack.reach = 2;
+            }
+            Some(Advantage::Health) => {
+                health.0 = HEALTH_ADVANTAGE_HEALTH;
+                max_health.0 = HEALTH_ADVANTAGE_HEALTH;
+            }
             None => panic!("no advantage was selected"),
         }
     }
 }
 
-fn get_nearest_tile_on_grid(x: f32, y: f32) -> (i32, i32) {
-    ((x / TILE_SIZE as f32) as i32, (y / TILE_SIZE as f32) as i32)
-}
-
-fn is_position_in_bounds(x_or_y: f32) -> bool {
-    let world_size_pixels = TILE_SIZE as f32 * WORLD_SIZE as f32;
-    x_or_y < world_size_pixels && x_or_y > 0.
+fn level_uid(
+    level_entity: Entity,
+    level_query: &Query<&Handle<LdtkLevel>>,
+    ldtk_levels: &Assets<LdtkLevel>,
+) -> Option<i32> {
+    level_query
+        .get(level_entity)
+        .ok()
+        .and_then(|level_handle| ldtk_levels.get(level_handle))
+        .map(|ldtk_level| ldtk_level.level.uid)
 }
 
-// Return top-left
-#[allow(dead_code)]
-fn tile_pos_to_sprite_pos(x: i32, y: i32) -> Vec3 {
-    let size = TILE_SIZE as f32;
-    let half = size / 2.;
-    Vec3::new(x as f32 * size + half, y as f32 * size + half, 100.)
+pub fn track_loaded_levels(
+    mut commands: Commands,
+    mut level_events: EventReader<LevelEvent>,
+    mut loaded_levels: ResMut<LoadedLevels>,
+    mut tile_map: ResMut<TileMap>,
+    level_query: Query<(&Transform, &Handle<LdtkLevel>)>,
+    effect_query: Query<(Entity, &GridPos), Or<(With<Debris>, With<EnemyTelegraph>)>>,
+    shard_query: Query<(Entity, &IceShard)>,
+    ldtk_assets: Res<Assets<LdtkAsset>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
+) {
+    for level_event in level_events.iter() {
+        match level_event {
+            LevelEvent::Spawned(uid) => {
+                let index = ldtk_assets
+                    .iter()
+                    .find_map(|(_, ldtk_asset)| {
+                        ldtk_asset.project.levels.iter().position(|l| l.uid == *uid)
+                    })
+                    .unwrap_or_default();
+
+                for (level_transform, level_handle) in level_query.iter() {
+                    if let Some(ldtk_level) = ldtk_levels.get(level_handle) {
+                        if ldtk_level.level.uid == *uid {
+                            loaded_levels.insert(
+                                &ldtk_level.level,
+                                index,
+                                level_transform.translation.truncate(),
+                            );
+                        }
+                    }
+                }
+            }
+            LevelEvent::Despawned(uid) => {
+                // Forget the walls and ladders of levels that aren't there anymore
+                if let Some(level_dimensions) = loaded_levels.remove(*uid) {
+                    tile_map
+                        .0
+                        .retain(|tile_pos, _| !level_dimensions.contains(*tile_pos));
+
+                    // Along with what was left lying around in them, which isn't part of
+                    // the level itself and would otherwise turn up in whatever comes next
+                    for (entity, grid_pos) in effect_query.iter() {
+                        if level_dimensions.contains(grid_pos.0) {
+                            commands.entity(entity).despawn();
+                        }
+                    }
+                    for (entity, shard) in shard_query.iter() {
+                        if level_dimensions.contains(shard.grid_pos) {
+                            commands.entity(entity).despawn();
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 pub fn build_tilemap_with_added_tiles(
     mut tile_map: ResMut<TileMap>,
-    wall_query: Query<&Transform, Added<WallTile>>,
-    climbable_query: Query<&Transform, Added<ClimbableTile>>,
+    loaded_levels: Res<LoadedLevels>,
+    wall_query: Query<(&GridCoords, &Parent), Added<WallTile>>,
+    climbable_query: Query<(&GridCoords, &Parent), Added<ClimbableTile>>,
+    mut crumbling_query: Query<(&GridCoords, &Parent, &mut CrumblingTile), Added<CrumblingTile>>,
+    slick_query: Query<(&GridCoords, &Parent), Added<SlickTile>>,
+    layer_query: Query<&Parent, Without<GridCoords>>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
 ) {
-    for wall_transform in wall_query.iter() {
-        tile_map.0.insert(
-            get_nearest_tile_on_grid(wall_transform.translation.x, wall_transform.translation.y),
-            TileType::Wall,
-        );
+    // Tiles are children of their layer, which is a child of the level
+    let origin_of_layer = |layer_entity: Entity| {
+        layer_query
+            .get(layer_entity)
+            .ok()
+            .and_then(|level_entity| level_uid(level_entity.0, &level_query, &ldtk_levels))
+            .map(|uid| loaded_levels.origin_of(uid))
+            .unwrap_or(IVec2::ZERO)
+    };
+
+    for (wall_coords, layer_entity) in wall_query.iter() {
+        let tile_pos = IVec2::from(*wall_coords) + origin_of_layer(layer_entity.0);
+        tile_map.0.insert(tile_pos, TileType::Wall);
     }
-    for climbable_transform in climbable_query.iter() {
-        tile_map.0.insert(
-            get_nearest_tile_on_grid(
-                climbable_transform.translation.x,
-                climbable_transform.translation.y,
-            ),
-            TileType::Ladder,
-        );
+    for (climbable_coords, layer_entity) in climbable_query.iter() {
+        let tile_pos = IVec2::from(*climbable_coords) + origin_of_layer(layer_entity.0);
+        tile_map.0.insert(tile_pos, TileType::Ladder);
+    }
+    for (crumbling_coords, layer_entity, mut crumbling) in crumbling_query.iter_mut() {
+        let tile_pos = IVec2::from(*crumbling_coords) + origin_of_layer(layer_entity.0);
+        tile_map.0.insert(tile_pos, TileType::Crumbling);
+        crumbling.grid_pos = tile_pos;
+    }
+    for (slick_coords, layer_entity) in slick_query.iter() {
+        let tile_pos = IVec2::from(*slick_coords) + origin_of_layer(layer_entity.0);
+        tile_map.0.insert(tile_pos, TileType::Slick);
     }
 }
 
-pub fn move_player_from_input(
-    input: Res<Input<KeyCode>>,
-    tile_map: Res<TileMap>,
-    mut game_state: ResMut<GameState>,
-    mut player_query: Query<
-        (&Speed, &mut Health, &mut Transform),
-        (With<Player>, Without<FallingIce>),
+// LDtk spawns entities relative to their level, move them to where that level is in the world
+pub fn place_spawned_entities_in_world(
+    mut commands: Commands,
+    loaded_levels: Res<LoadedLevels>,
+    mut query: Query<
+        (
+            Entity,
+            &mut GridPos,
+            Option<&mut Tween>,
+            Option<&mut Patrol>,
+            Option<&mut IceSettings>,
+            Option<&mut Switch>,
+            Option<&Worldly>,
+            &Parent,
+        ),
+        Added<GridPos>,
     >,
-    game_sounds: Res<GameSounds>,
-    audio: Res<Audio>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    ldtk_levels: Res<Assets<LdtkLevel>>,
 ) {
-    game_state.world_should_update = false;
-
-    if let Ok((player_speed, mut player_health, mut player_transform)) =
-        player_query.get_single_mut()
+    for (entity, mut grid_pos, tween, patrol, ice_settings, switch, worldly, parent) in
+        query.iter_mut()
     {
-        // Then move the player
-        let mut direction = (0.0, 0.0);
-        if input.just_pressed(KeyCode::A) || input.just_pressed(KeyCode::Left) {
-            direction.0 -= 1.0;
-        }
-        if input.just_pressed(KeyCode::D) || input.just_pressed(KeyCode::Right) {
-            direction.0 += 1.0;
-        }
-        if input.just_pressed(KeyCode::W) || input.just_pressed(KeyCode::Up) {
-            direction.1 += 1.0;
-        }
-        if input.just_pressed(KeyCode::S) || input.just_pressed(KeyCode::Down) {
-            direction.1 -= 1.0;
-        }
-
-        // reset falling state now that player moved (last frame)
-        let current_position = player_transform.translation.clone();
-        let mut tile_under_player =
-            get_nearest_tile_on_grid(current_position.x, current_position.y);
-        tile_under_player.1 -= 1;
-        game_state.player_is_falling = match tile_map.0.get(&tile_under_player) {
-            Some(_) => false,
-            None => true,
+        // Worldly entities have already been moved out of their level by now
+        let uid = match worldly {
+            Some(worldly) => Some(worldly.spawn_level),
+            None => level_uid(parent.0, &level_query, &ldtk_levels),
         };
 
-        let mut new_position = current_position.clone();
-        new_position.x += TILE_SIZE as f32 * direction.0;
-        new_position.y += TILE_SIZE as f32 * direction.1;
-
-        let going_down_while_falling = direction.1 < 0. && game_state.player_is_falling;
-        let mut new_position_is_valid = (
-            true,
-            new_position.y == current_position.y || going_down_while_falling,
-        );
-        if going_down_while_falling {
-            direction.1 = 0.0; // gravity will take care of it
-        }
-
-        // Fetch tile where the player wants to go
-        if let Some(tile) = tile_map
-            .0
-            .get(&get_nearest_tile_on_grid(new_position.x, new_position.y))
-        {
-            // Fetch tile below this one
-            let mut wall_is_under = false;
-            if let Some(tile_below) = tile_map.0.get(&get_nearest_tile_on_grid(
-                new_position.x,
-                new_position.y - 1.0,
-            )) {
-                wall_is_under = match *tile_below {
-                    TileType::Wall => true,
-                    _ => false,
-                };
+        if let Some(uid) = uid {
+            let origin = loaded_levels.origin_of(uid);
+            grid_pos.0 += origin;
+            commands.entity(entity).insert(SpawnTile(grid_pos.0));
+            if let Some(mut tween) = tween {
+                tween.from = grid_pos.0;
             }
-
-            match tile {
-                TileType::Wall => {
-                    new_position_is_valid.0 = wall_is_under;
+            if let Some(mut patrol) = patrol {
+                for waypoint in patrol.waypoints.iter_mut() {
+                    *waypoint += origin;
+                }
+            }
+            if let Some(mut ice_settings) = ice_settings {
+                ice_settings.home += origin;
+                if let IceTrigger::PressurePlate(plate_pos) = &mut ice_settings.trigger {
+                    *plate_pos += origin;
                 }
-                TileType::Ladder => {
-                    new_position_is_valid.1 = true;
+            }
+            if let Some(mut switch) = switch {
+                for target_tile in switch.target_tiles.iter_mut() {
+                    *target_tile += origin;
                 }
             }
         }
+    }
+}
 
-        if is_position_in_bounds(new_position.x)
-            && new_position != current_position
-            && new_position_is_valid.0
-            && new_position_is_valid.1
-        {
-            game_state.player_previous_pos = player_transform.translation;
-            player_transform.translation = new_position;
-
-            game_state.player_num_actions_taken += 1;
-            if game_state.player_num_actions_taken % player_speed.0 as u32 == 0 {
-                game_state.world_should_update = true;
-            }
+// Every level has a player in it, keep the one from the level being played.
+// Runs before the update so the others are gone by the time gameplay systems look for the player.
+pub fn despawn_duplicate_players(
+    mut commands: Commands,
+    player_query: Query<(Entity, &Worldly, ChangeTrackers<Player>)>,
+    level_selection: Res<LevelSelection>,
+    ldtk_query: Query<&Handle<LdtkAsset>>,
+    ldtk_assets: Res<Assets<LdtkAsset>>,
+) {
+    if player_query.iter().count() <= 1 {
+        return;
+    }
 
-            apply_gravity(
-                &tile_map,
-                &mut game_state,
-                current_position.clone(),
-                &mut player_transform,
-                &mut player_health,
-            );
+    let selected_uid = ldtk_query
+        .iter()
+        .filter_map(|ldtk_handle| ldtk_assets.get(ldtk_handle))
+        .find_map(|ldtk_asset| ldtk_asset.get_level(&level_selection))
+        .map(|level| level.uid);
+
+    let mut kept_player = player_query
+        .iter()
+        .find(|(_, _, tracker)| !tracker.is_added())
+        .or_else(|| {
+            player_query
+                .iter()
+                .find(|(_, worldly, _)| Some(worldly.spawn_level) == selected_uid)
+        })
+        .map(|(entity, _, _)| entity);
 
-            let mut rng = rand::thread_rng();
-            if !game_state.player_is_falling {
-                if direction.1 > 0. {
-                    audio.play(
-                        game_sounds.player_climb_up_sfxs
-                            [rng.gen_range(0..game_sounds.player_climb_up_sfxs.len())]
-                        .clone(),
-                    );
-                } else if direction.1 < 0. {
-                    audio.play(
-                        game_sounds.player_climb_down_sfxs
-                            [rng.gen_range(0..game_sounds.player_climb_down_sfxs.len())]
-                        .clone(),
-                    );
-                } else {
-                    audio.play(
-                        game_sounds.player_movement_sfxs
-                            [rng.gen_range(0..game_sounds.player_movement_sfxs.len())]
-                        .clone(),
-                    );
-                }
-            } else if player_health.0 <= 0 {
-                audio.play(
-                    game_sounds.player_hit_sfxs
-                        [rng.gen_range(0..game_sounds.player_hit_sfxs.len())]
-                    .clone(),
-                );
-            }
+    for (entity, _, _) in player_query.iter() {
+        if kept_player.is_none() {
+            kept_player = Some(entity);
+        } else if kept_player != Some(entity) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Walking off the edge of a level selects its neighbour, so the plugin
+// loads the levels around that one instead
+pub fn follow_player_into_neighbour_level(
+    world_layout: Res<WorldLayout>,
+    loaded_levels: Res<LoadedLevels>,
+    mut game_state: ResMut<GameState>,
+    mut level_selection: ResMut<LevelSelection>,
+    mut speedrun_timer: ResMut<SpeedrunTimer>,
+    run_stats: Res<RunStats>,
+    time: Res<Time>,
+    player_query: Query<&GridPos, (With<Player>, Changed<GridPos>)>,
+) {
+    if *world_layout != WorldLayout::Connected {
+        return;
+    }
+
+    if let Ok(player_pos) = player_query.get_single() {
+        if let Some(level_dimensions) = loaded_levels.level_at(player_pos.0) {
+            if level_dimensions.index != game_state.level_index {
+                // Climbing into a level for the first time finishes the one below it.
+                // Going back down doesn't start anything over, the moves still count.
+                if level_dimensions.index == speedrun_timer.splits.len() + 1 {
+                    record_split(&mut speedrun_timer, &run_stats, &game_state, &time);
+                    game_state.start_level(level_dimensions.index);
+                } else {
+                    game_state.level_index = level_dimensions.index;
+                }
+                *level_selection = LevelSelection::Index(level_dimensions.index);
+            }
+        }
+    }
+}
+
+const MAX_BUFFERED_MOVES: usize = 2;
+// How many world ticks a crumbling tile holds up after something first stands on it
+const CRUMBLE_TICKS: u32 = 2;
+const DEBRIS_TICKS: u32 = 6;
+const ICE_SHARD_COUNT: usize = 8;
+const ICE_SHARD_LIFETIME: f32 = 0.5; // seconds
+const ICE_SHARD_GRAVITY: f32 = 900.0; // pixels per second squared
+const ICE_SHAKE_FREQUENCY: f32 = 40.0; // radians per second
+const ICE_SHAKE_AMPLITUDE: f32 = 3.0; // pixels
+const SCREEN_SHAKE_DURATION: f32 = 0.3; // seconds
+const SCREEN_SHAKE_PER_TILE: f32 = 2.0; // pixels per tile fallen
+const SCREEN_SHAKE_MAX_AMPLITUDE: f32 = 16.0; // pixels
+
+pub fn buffer_player_input(input: Res<Input<KeyCode>>, mut game_state: ResMut<GameState>) {
+    let mut direction = IVec2::ZERO;
+    if input.just_pressed(KeyCode::A) || input.just_pressed(KeyCode::Left) {
+        direction.x -= 1;
+    }
+    if input.just_pressed(KeyCode::D) || input.just_pressed(KeyCode::Right) {
+        direction.x += 1;
+    }
+    if input.just_pressed(KeyCode::W) || input.just_pressed(KeyCode::Up) {
+        direction.y += 1;
+    }
+    if input.just_pressed(KeyCode::S) || input.just_pressed(KeyCode::Down) {
+        direction.y -= 1;
+    }
+
+    // Holding space swings at the tile in that direction instead of stepping onto it
+    let action = if input.pressed(KeyCode::Space) {
+        PlayerAction::Attack(direction)
+    } else {
+        PlayerAction::Move(direction)
+    };
+
+    // Keep moves made while the player is still animating so they aren't lost,
+    // but not so many that the player ends up far ahead of what's on screen
+    if direction != IVec2::ZERO && game_state.player_buffered_actions.len() < MAX_BUFFERED_MOVES {
+        game_state.player_buffered_actions.push_back(action);
+    }
+}
+
+// The world only moves every so many player actions, depending on their speed
+fn end_player_turn(game_state: &mut GameState, player_speed: &Speed) {
+    game_state.player_num_actions_taken += 1;
+    game_state.level_moves += 1;
+    if game_state.player_num_actions_taken % player_speed.0 as u32 == 0 {
+        game_state.world_should_update = true;
+    }
+}
+
+// What a swing can hurt, ice being shattered rather than hurt
+#[derive(SystemParam)]
+pub struct AttackTargets<'w, 's> {
+    health_query: Query<'w, 's, &'static mut Health, Without<Player>>,
+    ice_query: Query<'w, 's, &'static Transform, Or<(With<StaticIce>, With<FallingIce>)>>,
+}
+
+// The parts of the world a swing can reach and break, lent by the system swinging
+struct AttackWorld<'a, 'w, 's> {
+    tile_map: &'a TileMap,
+    loaded_levels: &'a LoadedLevels,
+    occupancy: &'a mut Occupancy,
+    attack_targets: &'a mut AttackTargets<'w, 's>,
+}
+
+// Hits whatever is first within reach in the given direction, walls stop the swing short
+fn attack_from(
+    commands: &mut Commands,
+    world: AttackWorld,
+    from: IVec2,
+    direction: IVec2,
+    attack: &Attack,
+    run_stats: &mut RunStats,
+) {
+    let AttackWorld {
+        tile_map,
+        loaded_levels,
+        occupancy,
+        attack_targets,
+    } = world;
+
+    for distance in 1..=attack.reach {
+        let target_pos = from + direction * distance;
+
+        let targets: Vec<(Entity, Role)> = occupancy
+            .at(target_pos)
+            .filter(|(_, role)| {
+                matches!(
+                    role,
+                    Role::Obstacle | Role::Enemy | Role::StaticIce | Role::FallingIce
+                )
+            })
+            .collect();
+        if !targets.is_empty() {
+            for (entity, role) in targets {
+                let is_destroyed = match role {
+                    // Ice breaks at the first hit
+                    Role::StaticIce | Role::FallingIce => {
+                        if let Ok(transform) = attack_targets.ice_query.get(entity) {
+                            shatter_ice(
+                                commands,
+                                loaded_levels,
+                                target_pos,
+                                transform.translation.z,
+                            );
+                        }
+                        true
+                    }
+                    _ => match attack_targets.health_query.get_mut(entity) {
+                        Ok(mut health) => {
+                            run_stats.damage_dealt += attack.damage.min(health.0.max(0));
+                            health.0 -= attack.damage;
+                            health.0 <= 0
+                        }
+                        Err(_) => false,
+                    },
+                };
+
+                if is_destroyed {
+                    commands.entity(entity).despawn();
+                    occupancy.remove(entity);
+                    if role == Role::Obstacle {
+                        run_stats.obstacles_destroyed += 1;
+                    }
+                }
+            }
+            return;
+        }
+
+        if tile_map
+            .0
+            .get(&target_pos)
+            .map_or(false, |tile| tile.is_solid())
+        {
+            return;
+        }
+    }
+}
+
+fn move_on_grid(grid_pos: &mut GridPos, tween: &mut Tween, to: IVec2) {
+    grid_pos.0 = to;
+    tween.waypoints.push_back(to);
+}
+
+// The wall and ladder rules for taking a single step, shared by the player and enemies
+pub fn can_step(
+    tile_map: &TileMap,
+    loaded_levels: &LoadedLevels,
+    current_pos: IVec2,
+    new_pos: IVec2,
+    is_falling: bool,
+) -> bool {
+    let going_down_while_falling = new_pos.y < current_pos.y && is_falling;
+    let mut new_pos_is_valid = (true, new_pos.y == current_pos.y || going_down_while_falling);
+
+    // Fetch tile where it wants to go
+    if let Some(tile) = tile_map.0.get(&new_pos) {
+        // Fetch tile below this one
+        let wall_is_under = match tile_map.0.get(&(new_pos - IVec2::Y)) {
+            Some(tile_under) => tile_under.is_solid(),
+            None => false,
+        };
+
+        if tile.is_solid() {
+            // Only the terrain itself can be walked into
+            new_pos_is_valid.0 = wall_is_under && *tile == TileType::Wall;
+        } else {
+            new_pos_is_valid.1 = true;
+        }
+    }
+
+    loaded_levels.contains(new_pos)
+        && new_pos != current_pos
+        && new_pos_is_valid.0
+        && new_pos_is_valid.1
+}
+
+// Whether there's nothing holding up something standing at this position
+fn is_falling_at(tile_map: &TileMap, grid_pos: IVec2) -> bool {
+    let tile_type_under = tile_map.0.get(&(grid_pos - IVec2::Y));
+    let tile_type_on = tile_map.0.get(&grid_pos);
+
+    let mut is_falling = match tile_type_under {
+        Some(_) => false,
+        None => true,
+    };
+
+    // Keep falling on the top ladder tile
+    is_falling |= match tile_type_under {
+        Some(TileType::Ladder) => true,
+        Some(_) => false,
+        None => true,
+    } && match tile_type_on {
+        None => true,
+        Some(_) => false,
+    };
+
+    is_falling
+}
+
+// Unless the level lets them grab on, a falling player goes past ladders and only
+// stops on solid ground
+pub fn is_falling_past_ladder(
+    tile_map: &TileMap,
+    loaded_levels: &LoadedLevels,
+    game_state: &GameState,
+    grid_pos: IVec2,
+) -> bool {
+    game_state.player_fall_height > 0
+        && !loaded_levels.rules_at(grid_pos).grab_ladders
+        && matches!(
+            tile_map.0.get(&(grid_pos - IVec2::Y)),
+            None | Some(TileType::Ladder)
+        )
+}
+
+pub fn move_player_from_input(
+    mut commands: Commands,
+    mut tile_map: ResMut<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    mut occupancy: ResMut<Occupancy>,
+    mut game_state: ResMut<GameState>,
+    mut player_query: Query<
+        (
+            &Speed,
+            &Attack,
+            &StatusEffects,
+            &mut Health,
+            &mut GridPos,
+            &mut Tween,
+        ),
+        (With<Player>, Without<FallingIce>),
+    >,
+    mut block_query: Query<(&mut GridPos, &mut Tween), (With<PushBlock>, Without<Player>)>,
+    mut attack_targets: AttackTargets,
+    mut run_stats: ResMut<RunStats>,
+    last_checkpoint: Res<LastCheckpoint>,
+    time: Res<Time>,
+    mut stunned_turn_timer: ResMut<StunnedTurnTimer>,
+    game_sounds: Res<GameSounds>,
+    audio: Res<Audio>,
+) {
+    game_state.world_should_update = false;
+
+    // Hold still while the level comes back from the checkpoint
+    if last_checkpoint.is_restoring {
+        return;
+    }
+
+    if let Ok((
+        player_speed,
+        player_attack,
+        player_status,
+        mut player_health,
+        mut player_pos,
+        mut player_tween,
+    )) = player_query.get_single_mut()
+    {
+        // Wait for the last move to be done animating before taking the next one
+        if !player_tween.is_idle() {
+            return;
+        }
+
+        // Stunned players don't get a say, the world moves on without them until it wears off
+        if player_status.has(StatusKind::Stunned) {
+            game_state.player_buffered_actions.clear();
+            if stunned_turn_timer.0.tick(time.delta()).just_finished() {
+                game_state.world_should_update = true;
+            }
+            return;
+        }
+        stunned_turn_timer.0.reset();
+
+        // Then move the player
+        let action = match game_state.player_buffered_actions.pop_front() {
+            Some(action) => action,
+            None => return,
+        };
+
+        // A frozen player spends the turn thawing out instead
+        if player_status.has(StatusKind::Frozen) {
+            end_player_turn(&mut game_state, player_speed);
+            return;
+        }
+
+        // reset falling state now that player moved (last frame)
+        let current_pos = player_pos.0;
+        game_state.player_is_falling =
+            match tile_map.0.get(&(current_pos - IVec2::Y)) {
+                Some(_) => false,
+                None => true,
+            } || is_falling_past_ladder(&tile_map, &loaded_levels, &game_state, current_pos);
+
+        let mut direction = match action {
+            PlayerAction::Move(direction) => direction,
+            PlayerAction::Attack(direction) => {
+                // No swinging while falling
+                if !game_state.player_is_falling {
+                    attack_from(
+                        &mut commands,
+                        AttackWorld {
+                            tile_map: &tile_map,
+                            loaded_levels: &loaded_levels,
+                            occupancy: &mut occupancy,
+                            attack_targets: &mut attack_targets,
+                        },
+                        current_pos,
+                        direction,
+                        player_attack,
+                        &mut run_stats,
+                    );
+                    audio.play(game_sounds.player_attack_sfx.clone());
+                    end_player_turn(&mut game_state, player_speed);
+                }
+                return;
+            }
+        };
+
+        let new_pos = current_pos + direction;
+        let new_pos_is_valid = match occupancy.with_role_at(new_pos, Role::PushBlock).next() {
+            // Blocks only get pushed sideways, and from solid ground
+            Some(block_entity) => {
+                direction.y == 0
+                    && !game_state.player_is_falling
+                    && push_block(
+                        &mut tile_map,
+                        &loaded_levels,
+                        &occupancy,
+                        &mut block_query,
+                        block_entity,
+                        direction,
+                    )
+            }
+            None => {
+                // Walking into a locked door with a key opens it for good
+                if let Some(door_entity) = occupancy.with_role_at(new_pos, Role::Door).next() {
+                    if game_state.player_keys > 0 {
+                        game_state.player_keys -= 1;
+                        tile_map.0.remove(&new_pos);
+                        commands.entity(door_entity).despawn();
+                    }
+                }
+
+                can_step(
+                    &tile_map,
+                    &loaded_levels,
+                    current_pos,
+                    new_pos,
+                    game_state.player_is_falling,
+                )
+            }
+        };
+
+        if direction.y < 0 && game_state.player_is_falling {
+            direction.y = 0; // gravity will take care of it
+        }
+
+        if new_pos_is_valid {
+            game_state.player_previous_pos = current_pos;
+            game_state.player_has_moved = true;
+            move_on_grid(&mut player_pos, &mut player_tween, new_pos);
+
+            // Slick tiles keep the player going until something stops them
+            if direction.x != 0 {
+                let slide_direction = IVec2::new(direction.x, 0);
+                while let Some(TileType::Slick) = tile_map.0.get(&(player_pos.0 - IVec2::Y)) {
+                    let slide_pos = player_pos.0 + slide_direction;
+                    if !can_step(&tile_map, &loaded_levels, player_pos.0, slide_pos, false)
+                        || occupancy.has_role_at(slide_pos, Role::Obstacle)
+                        || occupancy.has_role_at(slide_pos, Role::Enemy)
+                        || occupancy.has_role_at(slide_pos, Role::PushBlock)
+                    {
+                        break;
+                    }
+                    move_on_grid(&mut player_pos, &mut player_tween, slide_pos);
+                }
+            }
+
+            end_player_turn(&mut game_state, player_speed);
+
+            apply_gravity(
+                &tile_map,
+                &loaded_levels,
+                &mut game_state,
+                current_pos,
+                &mut player_pos,
+                &mut player_tween,
+                &mut player_health,
+            );
+
+            let mut rng = rand::thread_rng();
+            if !game_state.player_is_falling {
+                if direction.y > 0 {
+                    audio.play(
+                        game_sounds.player_climb_up_sfxs
+                            [rng.gen_range(0..game_sounds.player_climb_up_sfxs.len())]
+                        .clone(),
+                    );
+                } else if direction.y < 0 {
+                    audio.play(
+                        game_sounds.player_climb_down_sfxs
+                            [rng.gen_range(0..game_sounds.player_climb_down_sfxs.len())]
+                        .clone(),
+                    );
+                } else {
+                    audio.play(
+                        game_sounds.player_movement_sfxs
+                            [rng.gen_range(0..game_sounds.player_movement_sfxs.len())]
+                        .clone(),
+                    );
+                }
+            } else if player_health.0 <= 0 {
+                audio.play(
+                    game_sounds.player_hit_sfxs
+                        [rng.gen_range(0..game_sounds.player_hit_sfxs.len())]
+                    .clone(),
+                );
+            }
+        }
+    }
+}
+
+// Slides the block one tile along, if there's room for it there.
+// Blocks never go onto ladders, so they never take the place of another tile in the TileMap.
+fn push_block(
+    tile_map: &mut TileMap,
+    loaded_levels: &LoadedLevels,
+    occupancy: &Occupancy,
+    block_query: &mut Query<(&mut GridPos, &mut Tween), (With<PushBlock>, Without<Player>)>,
+    block_entity: Entity,
+    direction: IVec2,
+) -> bool {
+    if let Ok((mut block_pos, mut block_tween)) = block_query.get_mut(block_entity) {
+        let pushed_pos = block_pos.0 + direction;
+        let is_free = loaded_levels.contains(pushed_pos)
+            && !tile_map.0.contains_key(&pushed_pos)
+            && occupancy.at(pushed_pos).next().is_none();
+
+        if is_free {
+            tile_map.0.remove(&block_pos.0);
+            tile_map.0.insert(pushed_pos, TileType::PushBlock);
+            move_on_grid(&mut block_pos, &mut block_tween, pushed_pos);
+        }
+        is_free
+    } else {
+        false
+    }
+}
+
+pub fn add_entity_tiles_to_tilemap(
+    mut tile_map: ResMut<TileMap>,
+    block_query: Query<&GridPos, Added<PushBlock>>,
+    gate_query: Query<(&GridPos, &Gate), Added<Gate>>,
+    door_query: Query<&GridPos, Added<Door>>,
+) {
+    for block_pos in block_query.iter() {
+        tile_map.0.insert(block_pos.0, TileType::PushBlock);
+    }
+    for (gate_pos, gate) in gate_query.iter() {
+        if !gate.is_open {
+            tile_map.0.insert(gate_pos.0, TileType::Gate);
+        }
+    }
+    for door_pos in door_query.iter() {
+        tile_map.0.insert(door_pos.0, TileType::Gate);
+    }
+}
+
+// Blocks fall one tile per world tick until they land on a tile or something that holds them up.
+// Hazards and enemies in the way get crushed.
+pub fn move_push_blocks(
+    mut commands: Commands,
+    mut tile_map: ResMut<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    mut occupancy: ResMut<Occupancy>,
+    mut game_state: ResMut<GameState>,
+    mut block_query: Query<(Entity, &mut GridPos, &mut Tween), (With<PushBlock>, Without<Player>)>,
+    mut player_query: Query<(&mut GridPos, &mut Tween, &mut Health), With<Player>>,
+    hazard_query: Query<&Damage, Or<(With<Obstacle>, With<Enemy>)>>,
+) {
+    for (block_entity, mut block_pos, mut block_tween) in block_query.iter_mut() {
+        let fallen_pos = block_pos.0 - IVec2::Y;
+        if tile_map.0.contains_key(&fallen_pos) {
+            continue;
+        }
+
+        let mut crushed_entities = Vec::new();
+        let mut is_held_up = false;
+        for (entity, role) in occupancy.at(fallen_pos) {
+            let is_hazard = match role {
+                Role::Enemy => true,
+                Role::Obstacle => hazard_query
+                    .get(entity)
+                    .map_or(false, |damage| damage.0 > 0),
+                _ => false,
+            };
+            if is_hazard {
+                crushed_entities.push(entity);
+            } else {
+                is_held_up = true;
+            }
+        }
+        if is_held_up {
+            continue;
+        }
+
+        for entity in crushed_entities {
+            commands.entity(entity).despawn();
+            occupancy.remove(entity);
+        }
+
+        let old_pos = block_pos.0;
+        tile_map.0.remove(&old_pos);
+        move_on_grid(&mut block_pos, &mut block_tween, fallen_pos);
+        if loaded_levels.contains(fallen_pos) {
+            tile_map.0.insert(fallen_pos, TileType::PushBlock);
+        } else {
+            commands.entity(block_entity).despawn();
+            occupancy.remove(block_entity);
+        }
+
+        // Whoever was standing on it falls along
+        if let Ok((mut player_pos, mut player_tween, mut player_health)) =
+            player_query.get_single_mut()
+        {
+            if player_pos.0 == old_pos + IVec2::Y {
+                let current_pos = player_pos.0;
+                apply_gravity(
+                    &tile_map,
+                    &loaded_levels,
+                    &mut game_state,
+                    current_pos,
+                    &mut player_pos,
+                    &mut player_tween,
+                    &mut player_health,
+                );
+            }
         }
     }
 }
 
 fn return_to_main_menu(
     tile_map: &mut ResMut<TileMap>,
+    occupancy: &mut ResMut<Occupancy>,
     app_state: &mut ResMut<State<AppState>>,
     game_state: &mut ResMut<GameState>,
 ) {
     tile_map.0.clear();
+    occupancy.clear();
     **game_state = GameState::default();
-    (*app_state).set(AppState::MainMenu).unwrap();
+    // Leaving wins over anything else that happened on the same frame, like reaching the goal
+    (*app_state).overwrite_set(AppState::MainMenu).unwrap();
 }
 
 pub fn exit_on_esc(
     input: Res<Input<KeyCode>>,
     mut tile_map: ResMut<TileMap>,
+    mut occupancy: ResMut<Occupancy>,
     mut app_state: ResMut<State<AppState>>,
     mut game_state: ResMut<GameState>,
 ) {
     if input.just_pressed(KeyCode::Escape) {
-        return_to_main_menu(&mut tile_map, &mut app_state, &mut game_state);
+        return_to_main_menu(
+            &mut tile_map,
+            &mut occupancy,
+            &mut app_state,
+            &mut game_state,
+        );
+    }
+}
+
+pub fn check_for_player_death(
+    mut tile_map: ResMut<TileMap>,
+    mut occupancy: ResMut<Occupancy>,
+    mut app_state: ResMut<State<AppState>>,
+    mut game_state: ResMut<GameState>,
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+    mut run_stats: ResMut<RunStats>,
+    loaded_levels: Res<LoadedLevels>,
+    mut player_query: Query<
+        (&mut Health, &mut StatusEffects, &mut GridPos, &mut Tween),
+        (With<Player>, Changed<Health>),
+    >,
+    mut level_set_query: Query<&mut LevelSet>,
+) {
+    if let Ok((mut player_health, mut player_status, mut player_pos, mut player_tween)) =
+        player_query.get_single_mut()
+    {
+        if player_health.0 > 0 {
+            return;
+        }
+
+        let snapshot = match &last_checkpoint.snapshot {
+            Some(snapshot) if loaded_levels.levels.contains_key(&snapshot.level_uid) => {
+                snapshot.clone()
+            }
+            _ => {
+                return_to_main_menu(
+                    &mut tile_map,
+                    &mut occupancy,
+                    &mut app_state,
+                    &mut game_state,
+                );
+                return;
+            }
+        };
+
+        // Back to the campfire, and have its level respawned underneath
+        player_health.0 = snapshot.player_health;
+        player_status.0.clear();
+        player_pos.0 = snapshot.player_pos;
+        *player_tween = Tween::starting_at(*player_pos);
+        player_tween.waypoints.push_back(snapshot.player_pos);
+
+        game_state.player_previous_pos = snapshot.player_pos;
+        game_state.player_buffered_actions.clear();
+        game_state.player_is_falling = false;
+        game_state.player_fall_height = 0;
+        game_state.player_keys = snapshot.player_keys;
+        game_state.level_deaths += 1;
+        run_stats.deaths += 1;
+        game_state.level_moves = snapshot.level_moves;
+
+        for mut level_set in level_set_query.iter_mut() {
+            level_set.uids.remove(&snapshot.level_uid);
+        }
+        last_checkpoint.is_restoring = true;
+    }
+}
+
+pub fn check_player_reached_goal(
+    player_query: Query<(&GridPos, &Health), (With<Player>, Changed<GridPos>)>,
+    world_layout: Res<WorldLayout>,
+    loaded_levels: Res<LoadedLevels>,
+    occupancy: Res<Occupancy>,
+    mut app_state: ResMut<State<AppState>>,
+    mut game_state: ResMut<GameState>,
+    collectibles: Res<Collectibles>,
+    mut save_data: ResMut<SaveData>,
+    mut level_result: ResMut<LevelResult>,
+    mut speedrun_timer: ResMut<SpeedrunTimer>,
+    run_stats: Res<RunStats>,
+    time: Res<Time>,
+    game_sounds: Res<GameSounds>,
+    audio: Res<Audio>,
+) {
+    if let Ok((player_pos, player_health)) = player_query.get_single() {
+        // Falling to death onto the goal doesn't count as reaching it
+        if player_health.0 <= 0 {
+            return;
+        }
+
+        if occupancy.has_role_at(player_pos.0, Role::Goal) {
+            if *world_layout == WorldLayout::Connected && game_state.generated_level.is_none() {
+                // The mountain is climbed by walking from one level into the next,
+                // only the summit's goal ends the climb
+                let level_index = loaded_levels
+                    .level_at(player_pos.0)
+                    .map(|level_dimensions| level_dimensions.index);
+                if level_index != Some(NUM_LEVELS - 1) {
+                    return;
+                }
+                game_state.level_index = NUM_LEVELS - 1;
+            }
+
+            // Getting there one move too late doesn't count
+            let rules = loaded_levels.rules_at(player_pos.0);
+            let moves = game_state.level_moves;
+            if rules.is_out_of_moves(moves) {
+                return;
+            }
+
+            // The finished level stays up behind its results until the player moves on,
+            // unless they're already on their way out
+            if app_state.push(AppState::LevelResults).is_err() {
+                return;
+            }
+
+            audio.play(game_sounds.goal_sfx.clone());
+
+            let stars = rules.stars(moves);
+            let (is_best, best) = match game_state.generated_level {
+                Some(GeneratedLevel::Daily(daily_challenge)) => {
+                    let date = daily_challenge.date();
+                    let is_best = save_data.record_daily_result(&date, moves, stars);
+                    (is_best, save_data.best_daily_result(&date))
+                }
+                // There's no beating a random climb, nobody gets it twice
+                Some(GeneratedLevel::Random { .. }) => (false, None),
+                None => {
+                    let is_best = save_data.record_result(game_state.level_index, moves, stars);
+
+                    record_split(&mut speedrun_timer, &run_stats, &game_state, &time);
+                    if speedrun_timer.splits.len() == NUM_LEVELS {
+                        save_data.record_splits(&speedrun_timer.splits);
+                    }
+
+                    (is_best, save_data.best_result(game_state.level_index))
+                }
+            };
+
+            if is_best {
+                save_data.save();
+            }
+            *level_result = LevelResult {
+                level_index: game_state.level_index,
+                level_name: loaded_levels
+                    .name_at(player_pos.0)
+                    .unwrap_or_default()
+                    .to_uppercase(),
+                moves,
+                par: rules.par,
+                stars,
+                is_best,
+                best,
+                damage_taken: game_state.level_damage_taken,
+                gems: collectibles.in_level(game_state.level_index),
+                deaths: game_state.level_deaths,
+                time: game_state.level_time,
+            };
+        }
+    }
+}
+
+// Going over a level's move limit fails it, the same as dying there would
+pub fn check_move_limit(
+    game_state: Res<GameState>,
+    loaded_levels: Res<LoadedLevels>,
+    mut player_query: Query<(&GridPos, &mut Health), With<Player>>,
+) {
+    if let Ok((player_pos, mut player_health)) = player_query.get_single_mut() {
+        let rules = loaded_levels.rules_at(player_pos.0);
+        if player_health.0 > 0 && rules.is_out_of_moves(game_state.level_moves) {
+            player_health.0 = 0;
+        }
+    }
+}
+
+pub fn setup_level_results(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    level_result: Res<LevelResult>,
+) {
+    let font = asset_server.load("fonts/Minecraft.ttf");
+    let stat_color = Color::rgb(0.9, 0.9, 0.9);
+
+    let mut moves = format!("MOVES: {}", level_result.moves);
+    if level_result.par > 0 {
+        moves += &format!(" (PAR {})", level_result.par);
+    }
+    let minutes = level_result.time as u32 / 60;
+    let seconds = level_result.time as u32 % 60;
+
+    let mut lines = vec![
+        (
+            format!("{} COMPLETE", level_result.level_name),
+            62.0,
+            Color::rgb_u8(234, 237, 194),
+        ),
+        (moves, 32.0, stat_color),
+        (
+            format!("DAMAGE TAKEN: {}", level_result.damage_taken),
+            32.0,
+            stat_color,
+        ),
+        (
+            format!("GEMS: {}/{}", level_result.gems.0, level_result.gems.1),
+            32.0,
+            Color::rgb_u8(120, 220, 255),
+        ),
+        (format!("DEATHS: {}", level_result.deaths), 32.0, stat_color),
+        (
+            format!("TIME: {}:{:02}", minutes, seconds),
+            32.0,
+            stat_color,
+        ),
+    ];
+
+    if let Some(stars) = level_result.stars {
+        lines.push((
+            format!("STARS: {}/3", stars),
+            42.0,
+            Color::rgb_u8(255, 214, 90),
+        ));
+    }
+    if level_result.is_best {
+        lines.push(("NEW BEST!".to_string(), 32.0, Color::rgb_u8(120, 220, 255)));
+    } else if let Some((best_moves, best_stars)) = level_result.best {
+        let mut best = format!("BEST: {} MOVES", best_moves);
+        if let Some(best_stars) = best_stars {
+            best += &format!(", {}/3 STARS", best_stars);
+        }
+        lines.push((best, 32.0, Color::rgb_u8(120, 220, 255)));
+    }
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+            ..Default::default()
+        })
+        .insert(LevelResultsUi)
+        .with_children(|parent| {
+            for (line, font_size, color) in lines {
+                parent.spawn_bundle(TextBundle {
+                    style: Style {
+                        margin: Rect::all(Val::Px(12.0)),
+                        ..Default::default()
+                    },
+                    text: Text::with_section(
+                        line,
+                        TextStyle {
+                            font: font.clone(),
+                            font_size,
+                            color,
+                        },
+                        Default::default(),
+                    ),
+                    ..Default::default()
+                });
+            }
+
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(250.0), Val::Px(65.0)),
+                        margin: Rect::all(Val::Px(30.0)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..Default::default()
+                    },
+                    color: Color::rgb_u8(116, 147, 226).into(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle {
+                        text: Text::with_section(
+                            CONTINUE_BUTTON_LABEL,
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 32.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                            },
+                            Default::default(),
+                        ),
+                        ..Default::default()
+                    });
+                });
+        });
+
+    commands.insert_resource(UiSounds {
+        button_clicked_sfx: asset_server.load("audio/SFX_PlayerClimb_02.ogg"),
+    });
+}
+
+// Moving on from the results goes to the next level, or to the credits after the last one.
+// Enter does the same as clicking continue.
+pub fn continue_from_results(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut interaction_query: Query<
+        (&Interaction, &mut UiColor, &Children),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut text_query: Query<&mut Text>,
+    player_query: Query<Entity, With<Player>>,
+    mut tile_map: ResMut<TileMap>,
+    mut occupancy: ResMut<Occupancy>,
+    mut app_state: ResMut<State<AppState>>,
+    mut game_state: ResMut<GameState>,
+    mut level_selection: ResMut<LevelSelection>,
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+    mut run_stats: ResMut<RunStats>,
+    time: Res<Time>,
+    ui_sounds: Res<UiSounds>,
+    audio: Res<Audio>,
+) {
+    let mut continue_clicked = false;
+    for (interaction, mut color, children) in interaction_query.iter_mut() {
+        let mut text = text_query.get_mut(children[0]).unwrap();
+        match *interaction {
+            Interaction::Clicked => {
+                audio.play(ui_sounds.button_clicked_sfx.clone());
+                continue_clicked = true;
+            }
+            Interaction::Hovered => {
+                *color = Color::rgb_u8(193, 238, 247).into();
+                text.sections[0].style.color = Color::rgb(0.3, 0.3, 0.3).into();
+            }
+            Interaction::None => {
+                *color = Color::rgb_u8(116, 147, 226).into();
+                text.sections[0].style.color = Color::rgb(0.9, 0.9, 0.9).into();
+            }
+        }
+    }
+
+    if !continue_clicked && !input.just_pressed(KeyCode::Return) {
+        return;
+    }
+
+    let next_level_index = game_state.level_index + 1;
+    game_state.start_level(next_level_index);
+    game_state.player_keys = 0;
+    last_checkpoint.snapshot = None;
+
+    // The player is worldly, so it doesn't go away with its level
+    for player_entity in player_query.iter() {
+        commands.entity(player_entity).despawn();
+    }
+
+    tile_map.0.clear();
+    occupancy.clear();
+    if game_state.generated_level.is_some() {
+        // There's just the one level to a generated climb
+        *game_state = GameState::default();
+        app_state.replace(AppState::MainMenu).unwrap();
+    } else if game_state.level_index == NUM_LEVELS {
+        run_stats.moves = game_state.player_num_actions_taken;
+        run_stats.time = time.seconds_since_startup() - run_stats.started_at;
+        *game_state = GameState::default();
+        app_state.replace(AppState::Credits).unwrap();
+    } else {
+        *level_selection = LevelSelection::Index(game_state.level_index);
+        app_state.pop().unwrap();
+    }
+}
+
+pub fn close_level_results(
+    mut commands: Commands,
+    results_query: Query<Entity, With<LevelResultsUi>>,
+) {
+    for entity in results_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<UiSounds>();
+}
+
+// Keeps count of everything the player has lost to hurting, in the current level and the run
+pub fn track_damage_taken(
+    mut game_state: ResMut<GameState>,
+    mut run_stats: ResMut<RunStats>,
+    player_query: Query<(Entity, &Health), (With<Player>, Changed<Health>)>,
+    mut last_health: Local<Option<(Entity, i32)>>,
+) {
+    if let Ok((player_entity, player_health)) = player_query.get_single() {
+        if let Some((last_entity, last_value)) = *last_health {
+            // A new player comes with a new level, so there's nothing to compare to
+            if last_entity == player_entity && player_health.0 < last_value {
+                let damage_taken = last_value - player_health.0.max(0);
+                game_state.level_damage_taken += damage_taken;
+                run_stats.damage_taken += damage_taken;
+            }
+        }
+        *last_health = Some((player_entity, player_health.0.max(0)));
+    }
+}
+
+pub fn tick_level_timer(time: Res<Time>, mut game_state: ResMut<GameState>) {
+    game_state.level_time += time.delta_seconds();
+}
+
+pub fn apply_gravity(
+    tile_map: &TileMap,
+    loaded_levels: &LoadedLevels,
+    game_state: &mut GameState,
+    current_pos: IVec2,
+    player_pos: &mut GridPos,
+    player_tween: &mut Tween,
+    mut player_health: &mut Health,
+) {
+    game_state.player_is_falling = is_falling_at(tile_map, player_pos.0)
+        || is_falling_past_ladder(tile_map, loaded_levels, game_state, player_pos.0);
+
+    if game_state.player_is_falling {
+        // Only go down if we're not going down already
+        if player_pos.0.y == current_pos.y {
+            let fallen_pos = player_pos.0 - IVec2::Y;
+            move_on_grid(player_pos, player_tween, fallen_pos);
+            game_state.player_fall_height += 1;
+        }
+
+        if !loaded_levels.contains(player_pos.0) {
+            player_health.0 = 0; // the player has fallen to their death
+        }
+    }
+}
+
+// A fall is over once gravity lets go of the player, and hurts by how far it went
+pub fn land_player(
+    mut game_state: ResMut<GameState>,
+    tile_map: Res<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    mut player_query: Query<(&GridPos, &mut Health), With<Player>>,
+    mut landed_events: EventWriter<PlayerLanded>,
+) {
+    if game_state.player_is_falling || game_state.player_fall_height == 0 {
+        return;
+    }
+    let height = std::mem::take(&mut game_state.player_fall_height);
+
+    if let Ok((player_pos, mut player_health)) = player_query.get_single_mut() {
+        let rules = loaded_levels.rules_at(player_pos.0);
+        let damage = landing_damage(&tile_map, &rules, player_pos.0, height, player_health.0);
+
+        if damage > 0 {
+            player_health.0 -= damage;
+        }
+        landed_events.send(PlayerLanded { height, damage });
+    }
+}
+
+// How much a fall of the given height hurts a player with the given health, landing here
+pub fn landing_damage(
+    tile_map: &TileMap,
+    rules: &LevelRules,
+    grid_pos: IVec2,
+    height: u32,
+    health: i32,
+) -> i32 {
+    // Catching a ladder on the way down doesn't hurt
+    let caught_ladder = rules.grab_ladders && tile_map.0.get(&grid_pos) == Some(&TileType::Ladder);
+    if caught_ladder {
+        0
+    } else {
+        rules.fall_damage(height).unwrap_or_else(|| health.max(0))
+    }
+}
+
+pub fn play_landing_sfx(
+    mut landed_events: EventReader<PlayerLanded>,
+    game_sounds: Res<GameSounds>,
+    audio: Res<Audio>,
+) {
+    for landed in landed_events.iter() {
+        if landed.damage > 0 {
+            let mut rng = rand::thread_rng();
+            audio.play(
+                game_sounds.player_hit_sfxs[rng.gen_range(0..game_sounds.player_hit_sfxs.len())]
+                    .clone(),
+            );
+        }
+    }
+}
+
+pub fn shake_screen_on_landing(
+    mut landed_events: EventReader<PlayerLanded>,
+    mut screen_shake: ResMut<ScreenShake>,
+) {
+    for landed in landed_events.iter() {
+        // Stepping off a ledge isn't worth shaking over
+        if landed.height < 2 {
+            continue;
+        }
+
+        screen_shake.amplitude = (landed.height as f32 * SCREEN_SHAKE_PER_TILE)
+            .min(SCREEN_SHAKE_MAX_AMPLITUDE)
+            .max(screen_shake.amplitude);
+        screen_shake.time_left = SCREEN_SHAKE_DURATION;
+    }
+}
+
+pub fn crumble_tiles(
+    mut tile_map: ResMut<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    mut game_state: ResMut<GameState>,
+    mut crumbling_query: Query<(&mut CrumblingTile, &mut Tile, &TileParent)>,
+    mut player_query: Query<(&mut GridPos, &mut Tween, &mut Health), With<Player>>,
+    enemy_query: Query<&GridPos, (With<Enemy>, Without<Player>)>,
+    mut map_query: MapQuery,
+) {
+    if let Ok((mut player_pos, mut player_tween, mut player_health)) = player_query.get_single_mut()
+    {
+        for (mut crumbling, mut tile, tile_parent) in crumbling_query.iter_mut() {
+            if tile_map.0.get(&crumbling.grid_pos) != Some(&TileType::Crumbling) {
+                continue; // already gone
+            }
+
+            let pos_over = crumbling.grid_pos + IVec2::Y;
+            let is_stood_on = player_pos.0 == pos_over
+                || enemy_query.iter().any(|enemy_pos| enemy_pos.0 == pos_over);
+
+            match crumbling.ticks_left {
+                None => {
+                    if is_stood_on {
+                        crumbling.ticks_left = Some(CRUMBLE_TICKS);
+                        // Show that it's about to give way
+                        tile.color.set_a(0.6);
+                        map_query.notify_chunk(tile_parent.chunk);
+                    }
+                }
+                Some(ticks_left) if ticks_left > 1 => {
+                    crumbling.ticks_left = Some(ticks_left - 1);
+                }
+                Some(_) => {
+                    crumbling.ticks_left = Some(0);
+                    tile_map.0.remove(&crumbling.grid_pos);
+                    tile.visible = false;
+                    map_query.notify_chunk(tile_parent.chunk);
+
+                    // Whoever was standing on it falls, enemies take care of it on their turn
+                    if player_pos.0 == pos_over {
+                        let current_pos = player_pos.0;
+                        apply_gravity(
+                            &tile_map,
+                            &loaded_levels,
+                            &mut game_state,
+                            current_pos,
+                            &mut player_pos,
+                            &mut player_tween,
+                            &mut player_health,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn apply_player_visual_effects(
+    tile_map: Res<TileMap>,
+    game_state: Res<GameState>,
+    game_textures: Res<GameTextures>,
+    mut player_query: Query<(&Speed, &GridPos, &mut Sprite, &mut Handle<Image>), With<Player>>,
+) {
+    if let Ok((player_speed, player_pos, mut sprite, mut texture)) = player_query.get_single_mut() {
+        if game_state.player_num_actions_taken % player_speed.0 as u32 == 1 {
+            sprite.color.set_a(0.5);
+        } else {
+            sprite.color.set_a(1.0);
+        }
+
+        let player_tile_pos = player_pos.0;
+        let under_tile_pos = player_tile_pos - IVec2::Y;
+        let over_tile_pos = player_tile_pos + IVec2::Y;
+
+        // Climing if it's not the top ladder tile
+        let player_is_climbing = match tile_map.0.get(&player_tile_pos) {
+            Some(TileType::Ladder) => true,
+            _ => false,
+        } && match tile_map.0.get(&over_tile_pos) {
+            Some(TileType::Ladder) => true,
+            _ => false,
+        };
+
+        // Falling if there's nothing underneath
+        let mut player_is_falling = match tile_map.0.get(&under_tile_pos) {
+            Some(_) => false,
+            None => true,
+        };
+
+        // But also if it's the top ladder tile
+        player_is_falling |= match tile_map.0.get(&player_tile_pos) {
+            None => true,
+            Some(_) => false,
+        } && match tile_map.0.get(&under_tile_pos) {
+            Some(TileType::Ladder) => true,
+            Some(_) => false,
+            None => false,
+        };
+
+        if let Some(advantage) = &game_state.player_advantage {
+            if player_is_climbing {
+                *texture = match advantage {
+                    Advantage::Health => game_textures.player_health_climbing.clone(),
+                    Advantage::Speed => game_textures.player_speed_climbing.clone(),
+                    Advantage::Strength => game_textures.player_strength_climbing.clone(),
+                };
+            } else if player_is_falling {
+                *texture = match advantage {
+                    Advantage::Health => game_textures.player_health_falling.clone(),
+                    Advantage::Speed => game_textures.player_speed_falling.clone(),
+                    Advantage::Strength => game_textures.player_strength_falling.clone(),
+                };
+            } else {
+                *texture = match advantage {
+                    Advantage::Health => game_textures.player_health_standing.clone(),
+                    Advantage::Speed => game_textures.player_speed_standing.clone(),
+                    Advantage::Strength => game_textures.player_strength_standing.clone(),
+                };
+            }
+        }
+    }
+}
+
+pub fn animate_tweens(
+    time: Res<Time>,
+    tween_settings: Res<TweenSettings>,
+    loaded_levels: Res<LoadedLevels>,
+    mut query: Query<(&mut Transform, &mut Tween, Option<&Parent>), With<GridPos>>,
+    parent_query: Query<&Transform, Without<GridPos>>,
+) {
+    for (mut transform, mut tween, parent) in query.iter_mut() {
+        if tween.is_idle() {
+            tween.elapsed = 0.0;
+            continue;
+        }
+
+        // Go through as many tiles as the elapsed time allows, so a long
+        // fall takes as long to animate no matter the framerate
+        tween.elapsed += time.delta_seconds();
+        let parent_offset = parent_translation(parent, &parent_query);
+        while let Some(&target) = tween.waypoints.front() {
+            let from = loaded_levels.grid_to_translation(tween.from) - parent_offset;
+            let to = loaded_levels.grid_to_translation(target) - parent_offset;
+            if tween.elapsed < tween_settings.step_duration {
+                let t = tween.elapsed / tween_settings.step_duration;
+                let z = transform.translation.z;
+                transform.translation = from.lerp(to, t).extend(z);
+                break;
+            }
+
+            tween.elapsed -= tween_settings.step_duration;
+            tween.from = target;
+            tween.waypoints.pop_front();
+            let z = transform.translation.z;
+            transform.translation = to.extend(z);
+        }
+    }
+}
+
+// Entities that don't animate just jump to where they are on the grid
+pub fn snap_transforms_to_grid(
+    loaded_levels: Res<LoadedLevels>,
+    mut query: Query<
+        (&GridPos, &mut Transform, Option<&Parent>),
+        (Changed<GridPos>, Without<Tween>),
+    >,
+    parent_query: Query<&Transform, Without<GridPos>>,
+) {
+    for (grid_pos, mut transform, parent) in query.iter_mut() {
+        let z = transform.translation.z;
+        let translation = loaded_levels.grid_to_translation(grid_pos.0)
+            - parent_translation(parent, &parent_query);
+        transform.translation = translation.extend(z);
+    }
+}
+
+// Grid positions are in the world, but transforms are relative to the level or world they're in
+fn parent_translation(
+    parent: Option<&Parent>,
+    parent_query: &Query<&Transform, Without<GridPos>>,
+) -> Vec2 {
+    parent
+        .and_then(|parent| parent_query.get(parent.0).ok())
+        .map(|parent_transform| parent_transform.translation.truncate())
+        .unwrap_or(Vec2::ZERO)
+}
+
+pub fn index_occupancy(
+    mut occupancy: ResMut<Occupancy>,
+    query: Query<(Entity, &GridPos, &Role), Or<(Changed<GridPos>, Changed<Role>)>>,
+) {
+    for (entity, grid_pos, role) in query.iter() {
+        occupancy.insert(entity, grid_pos.0, *role);
+    }
+}
+
+// Catches entities despawned without going through the rules, like when a level unloads
+pub fn prune_occupancy(mut occupancy: ResMut<Occupancy>, removed: RemovedComponents<GridPos>) {
+    for entity in removed.iter() {
+        occupancy.remove(entity);
+    }
+}
+
+pub fn run_if_world_should_update(game_state: Res<GameState>) -> ShouldRun {
+    if game_state.world_should_update {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+// Every wind zone counts down to its next gust, which blows the player along with it
+// unless they're holding onto a ladder
+pub fn blow_wind(
+    tile_map: Res<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    mut occupancy: ResMut<Occupancy>,
+    mut game_state: ResMut<GameState>,
+    mut zone_query: Query<(&mut WindZone, &GridPos), Without<Player>>,
+    mut player_query: Query<(Entity, &mut Health, &mut GridPos, &mut Tween), With<Player>>,
+    mut gust_events: EventWriter<WindGusted>,
+) {
+    let mut player = player_query.get_single_mut().ok();
+
+    for (mut zone, zone_pos) in zone_query.iter_mut() {
+        zone.ticks_until_gust = zone.ticks_until_gust.saturating_sub(1);
+        if zone.ticks_until_gust > 0 {
+            continue;
+        }
+        zone.ticks_until_gust = zone.interval;
+
+        if let Some((player_entity, player_health, player_pos, player_tween)) = &mut player {
+            let blown_from = player_pos.0;
+            let blown_pos = blown_from + zone.direction;
+            let holding_ladder = tile_map.0.get(&blown_from) == Some(&TileType::Ladder);
+
+            let pushed_player = zone.contains(zone_pos.0, blown_from)
+                && !holding_ladder
+                && blown_pos != blown_from
+                && can_be_knocked_into(&tile_map, &loaded_levels, &occupancy, blown_pos);
+            if pushed_player {
+                move_on_grid(player_pos, player_tween, blown_pos);
+                occupancy.insert(*player_entity, blown_pos, Role::Player);
+                apply_gravity(
+                    &tile_map,
+                    &loaded_levels,
+                    &mut game_state,
+                    blown_from,
+                    player_pos,
+                    player_tween,
+                    player_health,
+                );
+            }
+
+            if loaded_levels.uid_at(zone_pos.0) == loaded_levels.uid_at(blown_from) {
+                gust_events.send(WindGusted { pushed_player });
+            }
+        }
+    }
+}
+
+pub fn spawn_snowflakes(
+    mut commands: Commands,
+    loaded_levels: Res<LoadedLevels>,
+    zone_query: Query<(Entity, &WindZone), Added<WindZone>>,
+) {
+    let mut rng = rand::thread_rng();
+    let size = loaded_levels.grid_size as f32;
+
+    for (zone_entity, zone) in zone_query.iter() {
+        // The zone sits on the middle of its top left tile
+        let min = Vec2::new(-size / 2., size / 2. - zone.size.y as f32 * size);
+        let max = min + zone.size.as_vec2() * size;
+
+        commands.entity(zone_entity).with_children(|parent| {
+            for _ in 0..zone.size.x * zone.size.y * SNOWFLAKES_PER_TILE {
+                parent
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::rgba(1.0, 1.0, 1.0, rng.gen_range(0.4..0.9)),
+                            custom_size: Some(Vec2::splat(rng.gen_range(3.0..7.0))),
+                            ..Default::default()
+                        },
+                        transform: Transform::from_xyz(
+                            rng.gen_range(min.x..max.x),
+                            rng.gen_range(min.y..max.y),
+                            0.5,
+                        ),
+                        ..Default::default()
+                    })
+                    .insert(Snowflake {
+                        speed: rng.gen_range(30.0..70.0),
+                    });
+            }
+        });
+    }
+}
+
+// Snow drifts the way the wind blows, racing along during a gust, and wraps around
+// to stay inside its zone
+pub fn drift_snowflakes(
+    time: Res<Time>,
+    wind_strength: Res<WindStrength>,
+    loaded_levels: Res<LoadedLevels>,
+    zone_query: Query<(&WindZone, &Children)>,
+    mut snowflake_query: Query<(&Snowflake, &mut Transform)>,
+) {
+    let size = loaded_levels.grid_size as f32;
+    let speedup = 1. + SNOWFLAKE_GUST_SPEEDUP * wind_strength.0;
+
+    for (zone, children) in zone_query.iter() {
+        let min = Vec2::new(-size / 2., size / 2. - zone.size.y as f32 * size);
+        let extent = zone.size.as_vec2() * size;
+
+        for child in children.iter() {
+            if let Ok((snowflake, mut transform)) = snowflake_query.get_mut(*child) {
+                let velocity = Vec2::new(
+                    zone.direction.x as f32 * snowflake.speed * speedup,
+                    -SNOWFLAKE_FALL_SPEED,
+                );
+                let moved = transform.translation.truncate() + velocity * time.delta_seconds();
+                transform.translation.x = min.x + (moved.x - min.x).rem_euclid(extent.x);
+                transform.translation.y = min.y + (moved.y - min.y).rem_euclid(extent.y);
+            }
+        }
+    }
+}
+
+// The wind ambience swells with each gust nearby, then dies back down
+pub fn play_wind_ambience(
+    time: Res<Time>,
+    mut gust_events: EventReader<WindGusted>,
+    mut wind_strength: ResMut<WindStrength>,
+    audio: Res<Audio>,
+) {
+    let mut gusted = false;
+    for gust in gust_events.iter() {
+        // Louder still for the gust that's blowing the player about
+        let strength = if gust.pushed_player { 1.0 } else { 0.6 };
+        wind_strength.0 = wind_strength.0.max(strength);
+        gusted = true;
+    }
+
+    if !gusted && wind_strength.0 <= 0. {
+        return;
+    }
+    if !gusted {
+        wind_strength.0 = (wind_strength.0 - time.delta_seconds() / GUST_FADE_DURATION).max(0.);
+    }
+
+    audio.set_volume_in_channel(
+        AMBIENCE_CALM_VOLUME + (1. - AMBIENCE_CALM_VOLUME) * wind_strength.0,
+        &AudioChannel::new(AMBIENCE_CHANNEL.to_owned()),
+    );
+}
+
+// Counts down everyone's status effects, with bleeding taking its toll on the way
+pub fn tick_status_effects(
+    mut commands: Commands,
+    mut occupancy: ResMut<Occupancy>,
+    mut status_query: Query<(Entity, &mut StatusEffects, &mut Health, Option<&Player>)>,
+) {
+    for (entity, mut status, mut health, player) in status_query.iter_mut() {
+        if status.0.is_empty() {
+            continue;
+        }
+
+        if status.has(StatusKind::Bleeding) && health.0 > 0 {
+            health.0 -= BLEEDING_DAMAGE_PER_TICK;
+
+            // The player's death is dealt with along with every other way of dying
+            if health.0 <= 0 && player.is_none() {
+                commands.entity(entity).despawn();
+                occupancy.remove(entity);
+            }
+        }
+
+        status.tick();
+    }
+}
+
+pub fn apply_damage_to_player(
+    mut commands: Commands,
+    tile_map: Res<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    mut occupancy: ResMut<Occupancy>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Health,
+            &Damage,
+            &mut StatusEffects,
+            &mut GridPos,
+            &mut Tween,
+        ),
+        With<Player>,
+    >,
+    mut obstacle_query: Query<
+        (
+            Option<&Blocking>,
+            Option<&Knockback>,
+            Option<&InflictsStatus>,
+            &mut Health,
+            &Damage,
+            &GridPos,
+        ),
+        Without<Player>,
+    >,
+    mut game_state: ResMut<GameState>,
+    mut run_stats: ResMut<RunStats>,
+    game_sounds: Res<GameSounds>,
+    audio: Res<Audio>,
+) {
+    if let Ok((
+        player_entity,
+        mut player_health,
+        player_damage,
+        mut player_status,
+        mut player_pos,
+        mut player_tween,
+    )) = player_query.get_single_mut()
+    {
+        let overlapping_entities: Vec<(Entity, Role)> = occupancy
+            .at(player_pos.0)
+            .filter(|(_, role)| *role != Role::Player)
+            .collect();
+
+        let mut knocked_back = false;
+        for (obstacle_entity, obstacle_role) in overlapping_entities {
+            if let Ok((
+                obstacle_blocking,
+                obstacle_knockback,
+                obstacle_inflicts,
+                mut obstacle_health,
+                obstacle_damage,
+                obstacle_pos,
+            )) = obstacle_query.get_mut(obstacle_entity)
+            {
+                let mut obstacle_just_died = false;
+                if player_damage.0 > 0 && obstacle_health.0 > 0 {
+                    run_stats.damage_dealt += player_damage.0.min(obstacle_health.0);
+                    obstacle_health.0 -= player_damage.0;
+
+                    audio.play(game_sounds.player_attack_sfx.clone());
+
+                    if obstacle_health.0 <= 0 {
+                        commands.entity(obstacle_entity).despawn();
+                        occupancy.remove(obstacle_entity);
+                        obstacle_just_died = true;
+                        if obstacle_role == Role::Obstacle {
+                            run_stats.obstacles_destroyed += 1;
+                        }
+                    }
+                }
+
+                // A player already thrown clear isn't bounced back onto the tile they left
+                if !obstacle_just_died
+                    && !knocked_back
+                    && game_state.player_has_moved
+                    && obstacle_blocking.unwrap_or(&Blocking(false)).0
+                {
+                    move_on_grid(
+                        &mut player_pos,
+                        &mut player_tween,
+                        game_state.player_previous_pos,
+                    );
+                    occupancy.insert(player_entity, player_pos.0, Role::Player);
+                }
+
+                if obstacle_damage.0 > 0 && player_health.0 > 0 {
+                    player_health.0 -= obstacle_damage.0;
+
+                    let mut rng = rand::thread_rng();
+                    audio.play(
+                        game_sounds.player_hit_sfxs
+                            [rng.gen_range(0..game_sounds.player_hit_sfxs.len())]
+                        .clone(),
+                    );
+
+                    // Only the first hit gets to throw the player about
+                    let knockback = obstacle_knockback.map_or(IVec2::ZERO, |knockback| knockback.0);
+                    if !knocked_back && knockback != IVec2::ZERO && player_health.0 > 0 {
+                        let knocked_pos = obstacle_pos.0 + knockback;
+                        if can_be_knocked_into(&tile_map, &loaded_levels, &occupancy, knocked_pos) {
+                            let hit_pos = player_pos.0;
+                            move_on_grid(&mut player_pos, &mut player_tween, knocked_pos);
+                            occupancy.insert(player_entity, player_pos.0, Role::Player);
+
+                            // Being thrown upwards breaks a fall, and anything else can start one
+                            if knockback.y > 0 {
+                                game_state.player_fall_height = 0;
+                            }
+                            apply_gravity(
+                                &tile_map,
+                                &loaded_levels,
+                                &mut game_state,
+                                hit_pos,
+                                &mut player_pos,
+                                &mut player_tween,
+                                &mut player_health,
+                            );
+                            knocked_back = true;
+                        }
+                    }
+                }
+
+                if let Some(effect) = obstacle_inflicts.and_then(|inflicts| inflicts.0) {
+                    if player_health.0 > 0 {
+                        player_status.add(effect);
+                    }
+                }
+            }
+        }
+    }
+    game_state.player_has_moved = false;
+}
+
+// Knockback and wind only move the player into open space, never into terrain or other things
+fn can_be_knocked_into(
+    tile_map: &TileMap,
+    loaded_levels: &LoadedLevels,
+    occupancy: &Occupancy,
+    grid_pos: IVec2,
+) -> bool {
+    loaded_levels.contains(grid_pos)
+        && !tile_map.0.get(&grid_pos).map_or(false, TileType::is_solid)
+        && !occupancy.has_role_at(grid_pos, Role::Obstacle)
+        && !occupancy.has_role_at(grid_pos, Role::Enemy)
+        && !occupancy.has_role_at(grid_pos, Role::PushBlock)
+}
+
+// Spike sprites point up, so turn them to face the way they knock the player
+pub fn orient_obstacles(mut obstacle_query: Query<(&Knockback, &mut Transform), Added<Knockback>>) {
+    for (knockback, mut transform) in obstacle_query.iter_mut() {
+        if knockback.0 != IVec2::ZERO {
+            let angle = (-knockback.0.x as f32).atan2(knockback.0.y as f32);
+            transform.rotation = Quat::from_rotation_z(angle);
+        }
+    }
+}
+
+pub fn release_falling_ice(
+    mut commands: Commands,
+    tile_map: Res<TileMap>,
+    occupancy: Res<Occupancy>,
+    mut ice_query: Query<(
+        Entity,
+        &IceSettings,
+        &mut StaticIce,
+        Option<&mut IceWarning>,
+        Option<&Switchable>,
+        &GridPos,
+    )>,
+    player_query: Query<&GridPos, With<Player>>,
+    game_sounds: Res<GameSounds>,
+    audio: Res<Audio>,
+) {
+    let player_pos = player_query
+        .get_single()
+        .ok()
+        .map(|player_pos| player_pos.0);
+
+    for (entity, settings, mut static_ice, warning, switchable, ice_pos) in ice_query.iter_mut() {
+        if let Some(mut warning) = warning {
+            if warning.ticks_left > 1 {
+                warning.ticks_left -= 1;
+            } else {
+                release_ice(&mut commands, entity);
+                audio.play(game_sounds.falling_ice_sfx.clone());
+            }
+            continue;
+        }
+
+        static_ice.ticks_hanging += 1;
+        let is_triggered = match settings.trigger {
+            IceTrigger::Proximity => player_pos.map_or(false, |player_pos| {
+                is_player_under_ice(&tile_map, &occupancy, ice_pos.0, player_pos)
+            }),
+            IceTrigger::Timed { interval } => static_ice.ticks_hanging >= interval,
+            IceTrigger::PressurePlate(plate_pos) => {
+                is_pressure_plate_pressed(&occupancy, plate_pos)
+            }
+        };
+        // Switches linked to it release it too
+        let is_triggered =
+            is_triggered || switchable.map_or(false, |switchable| switchable.is_active);
+
+        if is_triggered {
+            if settings.warning_ticks > 0 {
+                commands.entity(entity).insert(IceWarning {
+                    ticks_left: settings.warning_ticks,
+                });
+            } else {
+                release_ice(&mut commands, entity);
+                audio.play(game_sounds.falling_ice_sfx.clone());
+            }
+        }
+    }
+}
+
+fn release_ice(commands: &mut Commands, entity: Entity) {
+    commands
+        .entity(entity)
+        .insert(FallingIce::default())
+        .insert(Role::FallingIce)
+        .remove::<StaticIce>()
+        .remove::<IceWarning>();
+}
+
+// Whether the player is in the same column as the ice, under it, with no wall
+// or other ice in between
+fn is_player_under_ice(
+    tile_map: &TileMap,
+    occupancy: &Occupancy,
+    ice_pos: IVec2,
+    player_pos: IVec2,
+) -> bool {
+    if player_pos.x != ice_pos.x || player_pos.y > ice_pos.y {
+        return false;
+    }
+
+    (player_pos.y..ice_pos.y)
+        .map(|y| IVec2::new(ice_pos.x, y))
+        .all(|pos| {
+            !matches!(tile_map.0.get(&pos), Some(tile) if tile.is_solid())
+                && !occupancy.has_role_at(pos, Role::StaticIce)
+        })
+}
+
+fn is_pressure_plate_pressed(occupancy: &Occupancy, plate_pos: IVec2) -> bool {
+    occupancy.at(plate_pos).any(|(_, role)| {
+        matches!(
+            role,
+            Role::Player | Role::Enemy | Role::Obstacle | Role::PushBlock
+        )
+    })
+}
+
+pub fn press_pressure_plates(
+    occupancy: Res<Occupancy>,
+    mut plate_query: Query<(&mut PressurePlate, &mut Switch, &GridPos, &mut Sprite)>,
+) {
+    for (mut plate, mut switch, plate_pos, mut sprite) in plate_query.iter_mut() {
+        let is_pressed = is_pressure_plate_pressed(&occupancy, plate_pos.0);
+        if plate.is_pressed != is_pressed {
+            plate.is_pressed = is_pressed;
+            switch.is_on = is_pressed;
+            sprite.color = PressurePlate::color(is_pressed);
+        }
+    }
+}
+
+pub fn pull_levers(
+    player_query: Query<&GridPos, With<Player>>,
+    mut lever_query: Query<(&mut Lever, &mut Switch, &GridPos, &mut Sprite)>,
+) {
+    let player_pos = player_query
+        .get_single()
+        .ok()
+        .map(|player_pos| player_pos.0);
+
+    for (mut lever, mut switch, lever_pos, mut sprite) in lever_query.iter_mut() {
+        let is_stood_on = player_pos == Some(lever_pos.0);
+        if lever.is_stood_on == is_stood_on {
+            continue;
+        }
+
+        lever.is_stood_on = is_stood_on;
+        if is_stood_on {
+            switch.is_on = !switch.is_on;
+            sprite.color = Lever::color(switch.is_on);
+        }
+    }
+}
+
+pub fn collect_keys(
+    mut commands: Commands,
+    mut occupancy: ResMut<Occupancy>,
+    mut game_state: ResMut<GameState>,
+    player_query: Query<&GridPos, With<Player>>,
+) {
+    if let Ok(player_pos) = player_query.get_single() {
+        let key_entities: Vec<Entity> = occupancy.with_role_at(player_pos.0, Role::Key).collect();
+        for key_entity in key_entities {
+            game_state.player_keys += 1;
+            commands.entity(key_entity).despawn();
+            occupancy.remove(key_entity);
+        }
+    }
+}
+
+// Gems are tracked by where they were placed, those already collected this run are
+// taken away again when their level spawns a second time
+pub fn track_placed_gems(
+    mut commands: Commands,
+    mut collectibles: ResMut<Collectibles>,
+    loaded_levels: Res<LoadedLevels>,
+    gem_query: Query<(Entity, &GridPos), Added<Gem>>,
+) {
+    for (gem_entity, gem_pos) in gem_query.iter() {
+        if let Some(level_dimensions) = loaded_levels.level_at(gem_pos.0) {
+            let level = collectibles
+                .levels
+                .entry(level_dimensions.index)
+                .or_default();
+            if level.collected.contains(&gem_pos.0) {
+                commands.entity(gem_entity).despawn();
+            } else {
+                level.placed.insert(gem_pos.0);
+            }
+        }
+    }
+}
+
+pub fn collect_gems(
+    mut commands: Commands,
+    mut occupancy: ResMut<Occupancy>,
+    mut collectibles: ResMut<Collectibles>,
+    mut save_data: ResMut<SaveData>,
+    loaded_levels: Res<LoadedLevels>,
+    player_query: Query<&GridPos, With<Player>>,
+    game_sounds: Res<GameSounds>,
+    audio: Res<Audio>,
+) {
+    if let Ok(player_pos) = player_query.get_single() {
+        let gem_entities: Vec<Entity> = occupancy.with_role_at(player_pos.0, Role::Gem).collect();
+        if gem_entities.is_empty() {
+            return;
+        }
+
+        for gem_entity in gem_entities {
+            commands.entity(gem_entity).despawn();
+            occupancy.remove(gem_entity);
+        }
+        audio.play_in_channel(
+            game_sounds.gem_sfx.clone(),
+            &AudioChannel::new(GEM_CHANNEL.to_owned()),
+        );
+
+        if let Some(level_dimensions) = loaded_levels.level_at(player_pos.0) {
+            let index = level_dimensions.index;
+            if let Some(level) = collectibles.levels.get_mut(&index) {
+                level.collected.insert(player_pos.0);
+            }
+
+            let (collected, placed) = collectibles.in_level(index);
+            if save_data.record_gems(index, collected, placed) {
+                save_data.save();
+            }
+        }
+    }
+}
+
+// Marks the end of the current level on the speedrun timer, counting from the start of the run
+fn record_split(
+    speedrun_timer: &mut SpeedrunTimer,
+    run_stats: &RunStats,
+    game_state: &GameState,
+    time: &Time,
+) {
+    speedrun_timer.splits.push(Split {
+        time: time.seconds_since_startup() - run_stats.started_at,
+        turns: game_state.player_num_actions_taken,
+    });
+}
+
+fn format_split_time(seconds: f64) -> String {
+    let tenths = (seconds.max(0.0) * 10.0) as u64;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+pub fn toggle_speedrun_timer(
+    input: Res<Input<KeyCode>>,
+    mut speedrun_timer: ResMut<SpeedrunTimer>,
+) {
+    if input.just_pressed(KeyCode::T) {
+        speedrun_timer.is_shown = !speedrun_timer.is_shown;
+    }
+}
+
+// The run so far, then each level's split and how far ahead or behind the best run it was
+pub fn update_speedrun_timer(
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+    speedrun_timer: Res<SpeedrunTimer>,
+    run_stats: Res<RunStats>,
+    game_state: Res<GameState>,
+    save_data: Res<SaveData>,
+    mut timer_query: Query<&mut Text, With<SpeedrunTimerText>>,
+) {
+    let mut text = match timer_query.get_single_mut() {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    if !speedrun_timer.is_shown {
+        text.sections.clear();
+        return;
+    }
+
+    let font = asset_server.load("fonts/Minecraft.ttf");
+    let style = |color| TextStyle {
+        font: font.clone(),
+        font_size: 32.0,
+        color,
+    };
+    let stat_color = Color::rgb_u8(234, 237, 194);
+
+    let mut sections = vec![TextSection {
+        value: format!(
+            "{}  TURNS {}",
+            format_split_time(time.seconds_since_startup() - run_stats.started_at),
+            game_state.player_num_actions_taken
+        ),
+        style: style(stat_color),
+    }];
+    for (index, split) in speedrun_timer.splits.iter().enumerate() {
+        sections.push(TextSection {
+            value: format!("\nLEVEL {}  {}", index + 1, format_split_time(split.time)),
+            style: style(stat_color),
+        });
+        if let Some(best) = save_data.best_splits.get(index) {
+            let delta = split.time - best.time;
+            let color = if delta <= 0.0 {
+                SPLIT_AHEAD_COLOR
+            } else {
+                SPLIT_BEHIND_COLOR
+            };
+            sections.push(TextSection {
+                value: format!("  {:+.1}", delta),
+                style: style(color),
+            });
+        }
+    }
+    text.sections = sections;
+}
+
+pub fn update_hud(
+    collectibles: Res<Collectibles>,
+    save_data: Res<SaveData>,
+    loaded_levels: Res<LoadedLevels>,
+    game_state: Res<GameState>,
+    player_query: Query<(&GridPos, &Health, &MaxHealth, &StatusEffects), With<Player>>,
+    mut hud_query: Query<&mut Text, With<HudText>>,
+) {
+    let player = player_query.get_single().ok();
+
+    // In a connected world the player's level is whichever one they're standing in
+    let index = player
+        .and_then(|(player_pos, _, _, _)| loaded_levels.level_at(player_pos.0))
+        .map(|level_dimensions| level_dimensions.index)
+        .unwrap_or(game_state.level_index);
+
+    let mut hud = String::new();
+    if let Some((player_pos, player_health, player_max_health, _)) = player {
+        hud += &format!(
+            "HEALTH {}/{}  ",
+            player_health.0.max(0),
+            player_max_health.0
+        );
+
+        let rules = loaded_levels.rules_at(player_pos.0);
+        hud += &format!("MOVES {}", game_state.level_moves);
+        if rules.move_limit > 0 {
+            hud += &format!("/{}", rules.move_limit);
+        }
+        if rules.par > 0 {
+            hud += &format!(" PAR {}", rules.par);
+        }
+        hud += "  ";
+    }
+
+    let (collected, placed) = collectibles.in_level(index);
+    hud += &format!("GEMS {}/{}", collected, placed);
+    if let Some(generated_level) = game_state.generated_level {
+        hud += &format!("  {}", generated_level.name().to_uppercase());
+    } else if let Some((best_collected, best_placed)) = save_data.best_gems(index) {
+        hud += &format!("  BEST {}/{}", best_collected, best_placed);
+    }
+
+    if let Some((_, _, _, player_status)) = player {
+        for effect in player_status.0.iter() {
+            hud += &format!("\n{} {}", effect.kind.label(), effect.ticks_left);
+        }
+    }
+
+    for mut text in hud_query.iter_mut() {
+        if text.sections[0].value != hud {
+            text.sections[0].value = hud.clone();
+        }
+    }
+}
+
+// Touching a campfire lights it, and remembers the level as the player left it so far
+pub fn light_checkpoints(
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+    loaded_levels: Res<LoadedLevels>,
+    game_state: Res<GameState>,
+    player_query: Query<(&GridPos, &Health, &MaxHealth), (With<Player>, Changed<GridPos>)>,
+    mut checkpoint_query: Query<(&mut Checkpoint, &GridPos, &mut Sprite)>,
+    spawned_query: Query<(&SpawnTile, &GridPos, Option<&Switch>), Without<Player>>,
+    crumbling_query: Query<&CrumblingTile>,
+) {
+    // The player lands on the campfire while its level is still being put back
+    if last_checkpoint.is_restoring {
+        return;
+    }
+
+    let (player_pos, player_health, player_max_health) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let health_refill = match checkpoint_query
+        .iter()
+        .find(|(_, checkpoint_pos, _)| checkpoint_pos.0 == player_pos.0)
+    {
+        Some((checkpoint, _, _)) => checkpoint.health_refill,
+        None => return,
+    };
+    let level_uid = match loaded_levels.uid_at(player_pos.0) {
+        Some(level_uid) => level_uid,
+        None => return,
+    };
+
+    for (mut checkpoint, checkpoint_pos, mut sprite) in checkpoint_query.iter_mut() {
+        checkpoint.is_lit = checkpoint_pos.0 == player_pos.0;
+        sprite.color = Checkpoint::color(checkpoint.is_lit);
+    }
+
+    last_checkpoint.snapshot = Some(LevelSnapshot {
+        level_uid,
+        player_pos: player_pos.0,
+        player_health: if health_refill > 0 {
+            health_refill.min(player_max_health.0)
+        } else {
+            player_health.0
+        },
+        player_keys: game_state.player_keys,
+        level_moves: game_state.level_moves,
+        entities: spawned_query
+            .iter()
+            .filter(|(spawn_tile, _, _)| loaded_levels.uid_at(spawn_tile.0) == Some(level_uid))
+            .map(|(spawn_tile, grid_pos, switch)| {
+                (
+                    spawn_tile.0,
+                    SnapshotEntity {
+                        pos: grid_pos.0,
+                        switch_is_on: switch.map(|switch| switch.is_on),
+                    },
+                )
+            })
+            .collect(),
+        crumbled_tiles: crumbling_query
+            .iter()
+            .filter(|crumbling| crumbling.ticks_left == Some(0))
+            .filter(|crumbling| loaded_levels.uid_at(crumbling.grid_pos) == Some(level_uid))
+            .map(|crumbling| crumbling.grid_pos)
+            .collect(),
+    });
+}
+
+// Once the checkpoint's level has despawned, ask for it again. When the fresh copy is in
+// the world, take away what was already gone at the checkpoint and put back what was moved.
+pub fn restore_checkpoint(
+    mut commands: Commands,
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+    mut tile_map: ResMut<TileMap>,
+    mut occupancy: ResMut<Occupancy>,
+    loaded_levels: Res<LoadedLevels>,
+    mut level_set_query: Query<&mut LevelSet>,
+    mut spawned_query: Query<
+        (
+            Entity,
+            &SpawnTile,
+            &mut GridPos,
+            Option<&mut Tween>,
+            Option<&mut Switch>,
+            Option<&mut Checkpoint>,
+            Option<&mut Sprite>,
+            Option<&Lever>,
+            Option<&PushBlock>,
+        ),
+        (Added<SpawnTile>, Without<Player>),
+    >,
+) {
+    if !last_checkpoint.is_restoring {
+        return;
+    }
+    let snapshot = match &last_checkpoint.snapshot {
+        Some(snapshot) => snapshot,
+        None => {
+            last_checkpoint.is_restoring = false;
+            return;
+        }
+    };
+
+    if !loaded_levels.levels.contains_key(&snapshot.level_uid) {
+        for mut level_set in level_set_query.iter_mut() {
+            if !level_set.uids.contains(&snapshot.level_uid) {
+                level_set.uids.insert(snapshot.level_uid);
+            }
+        }
+        return;
+    }
+
+    let mut is_restored = false;
+    for (entity, spawn_tile, mut grid_pos, tween, switch, checkpoint, sprite, lever, push_block) in
+        spawned_query.iter_mut()
+    {
+        if loaded_levels.uid_at(spawn_tile.0) != Some(snapshot.level_uid) {
+            continue;
+        }
+        is_restored = true;
+
+        let snapshot_entity = match snapshot.entities.get(&spawn_tile.0) {
+            Some(snapshot_entity) => snapshot_entity,
+            None => {
+                // Doors and blocks have already put themselves in the TileMap
+                if matches!(
+                    tile_map.0.get(&grid_pos.0),
+                    Some(TileType::PushBlock | TileType::Gate)
+                ) {
+                    tile_map.0.remove(&grid_pos.0);
+                }
+                commands.entity(entity).despawn();
+                occupancy.remove(entity);
+                continue;
+            }
+        };
+
+        if push_block.is_some() && grid_pos.0 != snapshot_entity.pos {
+            tile_map.0.remove(&grid_pos.0);
+            tile_map.0.insert(snapshot_entity.pos, TileType::PushBlock);
+            grid_pos.0 = snapshot_entity.pos;
+            if let Some(mut tween) = tween {
+                *tween = Tween::starting_at(*grid_pos);
+                tween.waypoints.push_back(snapshot_entity.pos);
+            }
+        }
+
+        if let (Some(mut switch), Some(is_on)) = (switch, snapshot_entity.switch_is_on) {
+            switch.is_on = is_on;
+            if let (Some(_), Some(mut sprite)) = (lever, sprite) {
+                sprite.color = Lever::color(is_on);
+            }
+        } else if let (Some(mut checkpoint), Some(mut sprite)) = (checkpoint, sprite) {
+            checkpoint.is_lit = grid_pos.0 == snapshot.player_pos;
+            sprite.color = Checkpoint::color(checkpoint.is_lit);
+        }
+    }
+
+    if is_restored {
+        last_checkpoint.is_restoring = false;
+    }
+}
+
+// Ledges that had crumbled by the checkpoint stay down in the fresh copy of its level
+pub fn restore_crumbled_tiles(
+    last_checkpoint: Res<LastCheckpoint>,
+    mut tile_map: ResMut<TileMap>,
+    mut crumbling_query: Query<(&mut CrumblingTile, &mut Tile, &TileParent), Added<CrumblingTile>>,
+    mut map_query: MapQuery,
+) {
+    let snapshot = match &last_checkpoint.snapshot {
+        Some(snapshot) if last_checkpoint.is_restoring => snapshot,
+        _ => return,
+    };
+
+    for (mut crumbling, mut tile, tile_parent) in crumbling_query.iter_mut() {
+        if snapshot.crumbled_tiles.contains(&crumbling.grid_pos) {
+            crumbling.ticks_left = Some(0);
+            tile_map.0.remove(&crumbling.grid_pos);
+            tile.visible = false;
+            map_query.notify_chunk(tile_parent.chunk);
+        }
     }
 }
 
-pub fn check_for_player_death(
-    mut tile_map: ResMut<TileMap>,
-    mut app_state: ResMut<State<AppState>>,
-    mut game_state: ResMut<GameState>,
-    player_query: Query<&Health, (With<Player>, Changed<Health>)>,
+pub fn collect_health_pickups(
+    mut commands: Commands,
+    mut occupancy: ResMut<Occupancy>,
+    mut player_query: Query<(&GridPos, &mut Health, &MaxHealth), With<Player>>,
+    pickup_query: Query<&HealthPickup>,
 ) {
-    if let Ok(player_health) = player_query.get_single() {
-        if player_health.0 <= 0 {
-            return_to_main_menu(&mut tile_map, &mut app_state, &mut game_state);
+    if let Ok((player_pos, mut player_health, player_max_health)) = player_query.get_single_mut() {
+        let pickup_entities: Vec<Entity> = occupancy
+            .with_role_at(player_pos.0, Role::HealthPickup)
+            .collect();
+        for pickup_entity in pickup_entities {
+            // Left where it is for later when there's nothing to heal
+            if player_health.0 <= 0 || player_health.0 >= player_max_health.0 {
+                break;
+            }
+
+            if let Ok(pickup) = pickup_query.get(pickup_entity) {
+                player_health.0 = (player_health.0 + pickup.heal).min(player_max_health.0);
+                commands.entity(pickup_entity).despawn();
+                occupancy.remove(pickup_entity);
+            }
         }
     }
 }
 
-pub fn check_player_reached_goal(
-    goal_query: Query<&Transform, With<Goal>>,
-    player_query: Query<&Transform, (With<Player>, Changed<Transform>)>,
-    mut tile_map: ResMut<TileMap>,
-    mut app_state: ResMut<State<AppState>>,
+// Resting and the level's own regeneration both take turns to heal
+pub fn heal_player(
     mut game_state: ResMut<GameState>,
-    game_sounds: Res<GameSounds>,
-    audio: Res<Audio>,
-    mut level_selection: ResMut<LevelSelection>,
+    loaded_levels: Res<LoadedLevels>,
+    occupancy: Res<Occupancy>,
+    mut player_query: Query<(&GridPos, &mut Health, &MaxHealth), With<Player>>,
+    rest_spot_query: Query<&RestSpot>,
 ) {
-    if let Ok(player_transform) = player_query.get_single() {
-        if let Ok(goal_transform) = goal_query.get_single() {
-            if entities_are_overlapping(player_transform, goal_transform) {
-                audio.play(game_sounds.goal_sfx.clone());
-
-                game_state.level_index += 1;
+    if let Ok((player_pos, mut player_health, player_max_health)) = player_query.get_single_mut() {
+        // Too late for the dead
+        if player_health.0 <= 0 {
+            return;
+        }
 
-                tile_map.0.clear();
-                if game_state.level_index == NUM_LEVELS {
-                    *game_state = GameState::default();
-                    app_state.set(AppState::Credits).unwrap();
-                } else {
-                    *level_selection = LevelSelection::Index(game_state.level_index);
-                }
+        let mut heal: i32 = occupancy
+            .with_role_at(player_pos.0, Role::RestSpot)
+            .filter_map(|entity| rest_spot_query.get(entity).ok())
+            .map(|rest_spot| rest_spot.heal_per_turn)
+            .sum();
+
+        let rules = loaded_levels.rules_at(player_pos.0);
+        if rules.regen_every > 0 {
+            game_state.turns_since_regen += 1;
+            if game_state.turns_since_regen >= rules.regen_every {
+                game_state.turns_since_regen = 0;
+                heal += rules.regen_amount;
             }
         }
+
+        if heal > 0 && player_health.0 < player_max_health.0 {
+            player_health.0 = (player_health.0 + heal).min(player_max_health.0);
+        }
     }
 }
 
-fn apply_gravity(
-    tile_map: &Res<TileMap>,
-    game_state: &mut ResMut<GameState>,
-    current_position: Vec3,
-    player_transform: &mut Transform,
-    mut player_health: &mut Health,
+// Switches find what they operate once everything in their level is in the world
+pub fn link_switches(
+    mut commands: Commands,
+    mut switch_query: Query<&mut Switch, Added<Switch>>,
+    target_query: Query<
+        (Entity, &GridPos, Option<&Obstacle>, Option<&Damage>),
+        Or<(With<Gate>, With<Obstacle>, With<StaticIce>)>,
+    >,
 ) {
-    let next_position = player_transform.translation.clone();
-    let mut tile_under_player = get_nearest_tile_on_grid(next_position.x, next_position.y);
-    tile_under_player.1 -= 1;
-    let tile_type_under = tile_map.0.get(&tile_under_player);
+    for mut switch in switch_query.iter_mut() {
+        for (entity, target_pos, obstacle, damage) in target_query.iter() {
+            if !switch.target_tiles.contains(&target_pos.0) {
+                continue;
+            }
 
-    let mut tile_on_player = tile_under_player;
-    tile_on_player.1 += 1;
-    let tile_type_on = tile_map.0.get(&tile_on_player);
+            switch.targets.push(entity);
+            commands.entity(entity).insert(Switchable::default());
+            if let (Some(_), Some(damage)) = (obstacle, damage) {
+                commands
+                    .entity(entity)
+                    .insert(Retractable { damage: damage.0 });
+            }
+        }
+    }
+}
 
-    game_state.player_is_falling = match tile_type_under {
-        Some(_) => false,
-        None => true,
-    };
+pub fn operate_switches(
+    switch_query: Query<&Switch>,
+    mut switchable_query: Query<(Entity, &mut Switchable)>,
+) {
+    let active_targets: HashSet<Entity> = switch_query
+        .iter()
+        .filter(|switch| switch.is_on)
+        .flat_map(|switch| switch.targets.iter().copied())
+        .collect();
+
+    for (entity, mut switchable) in switchable_query.iter_mut() {
+        let is_active = active_targets.contains(&entity);
+        if switchable.is_active != is_active {
+            switchable.is_active = is_active;
+        }
+    }
+}
 
-    // Keep falling on the top ladder tile
-    game_state.player_is_falling |= match tile_type_under {
-        Some(TileType::Ladder) => true,
-        Some(_) => false,
-        None => true,
-    } && match tile_type_on {
-        None => true,
-        Some(_) => false,
-    };
+pub fn open_gates(
+    mut tile_map: ResMut<TileMap>,
+    occupancy: Res<Occupancy>,
+    mut gate_query: Query<(&mut Gate, &Switchable, &GridPos, &mut Sprite)>,
+) {
+    for (mut gate, switchable, gate_pos, mut sprite) in gate_query.iter_mut() {
+        let should_be_open = gate.starts_open != switchable.is_active;
+        if gate.is_open == should_be_open {
+            continue;
+        }
 
-    if game_state.player_is_falling {
-        // Only go down if we're not going down already
-        if player_transform.translation.y == current_position.y {
-            player_transform.translation.y -= TILE_SIZE as f32;
+        // Wait for the way to be clear before shutting
+        if !should_be_open && occupancy.at(gate_pos.0).next().is_some() {
+            continue;
         }
 
-        if !is_position_in_bounds(player_transform.translation.y) {
-            player_health.0 = 0; // the player has fallen to their death
+        gate.is_open = should_be_open;
+        if should_be_open {
+            tile_map.0.remove(&gate_pos.0);
+        } else {
+            tile_map.0.insert(gate_pos.0, TileType::Gate);
         }
+        sprite.color = Gate::color(should_be_open);
     }
 }
 
-pub fn apply_player_visual_effects(
-    tile_map: Res<TileMap>,
-    game_state: Res<GameState>,
-    game_textures: Res<GameTextures>,
-    mut player_query: Query<(&Speed, &Transform, &mut Sprite, &mut Handle<Image>), With<Player>>,
+pub fn retract_spikes(
+    mut spike_query: Query<
+        (&Retractable, &Switchable, &mut Damage, &mut Sprite),
+        Changed<Switchable>,
+    >,
 ) {
-    if let Ok((player_speed, player_transform, mut sprite, mut texture)) =
-        player_query.get_single_mut()
-    {
-        if game_state.player_num_actions_taken % player_speed.0 as u32 == 1 {
-            sprite.color.set_a(0.5);
+    for (retractable, switchable, mut damage, mut sprite) in spike_query.iter_mut() {
+        if switchable.is_active {
+            damage.0 = 0;
+            sprite.color.set_a(0.3);
         } else {
+            damage.0 = retractable.damage;
             sprite.color.set_a(1.0);
         }
+    }
+}
 
-        let player_tile_pos = get_nearest_tile_on_grid(
-            player_transform.translation.x,
-            player_transform.translation.y,
-        );
-        let under_tile_pos = (player_tile_pos.0, player_tile_pos.1 - 1);
-        let over_tile_pos = (player_tile_pos.0, player_tile_pos.1 + 1);
+pub fn shake_warned_ice(
+    time: Res<Time>,
+    loaded_levels: Res<LoadedLevels>,
+    mut ice_query: Query<(&GridPos, &mut Transform, Option<&Parent>), With<IceWarning>>,
+    parent_query: Query<&Transform, Without<GridPos>>,
+) {
+    let shake =
+        (time.seconds_since_startup() as f32 * ICE_SHAKE_FREQUENCY).sin() * ICE_SHAKE_AMPLITUDE;
+    for (grid_pos, mut transform, parent) in ice_query.iter_mut() {
+        let x = loaded_levels.grid_to_translation(grid_pos.0).x
+            - parent_translation(parent, &parent_query).x;
+        transform.translation.x = x + shake;
+    }
+}
 
-        // Climing if it's not the top ladder tile
-        let player_is_climbing = match tile_map.0.get(&player_tile_pos) {
-            Some(TileType::Ladder) => true,
-            _ => false,
-        } && match tile_map.0.get(&over_tile_pos) {
-            Some(TileType::Ladder) => true,
-            _ => false,
-        };
+pub fn move_falling_ice(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut tile_map: ResMut<TileMap>,
+    mut occupancy: ResMut<Occupancy>,
+    loaded_levels: Res<LoadedLevels>,
+    mut ice_query: Query<(
+        Entity,
+        &mut FallingIce,
+        &Damage,
+        &IceSettings,
+        &InflictsStatus,
+        &Transform,
+        &mut GridPos,
+        &mut Tween,
+    )>,
+    mut target_query: Query<
+        (&mut Health, Option<&mut StatusEffects>),
+        (Or<(With<Obstacle>, With<Enemy>)>, Without<FallingIce>),
+    >,
+    mut run_stats: ResMut<RunStats>,
+    game_sounds: Res<GameSounds>,
+    audio: Res<Audio>,
+) {
+    for (entity, mut falling_ice, damage, settings, inflicts, transform, mut grid_pos, mut tween) in
+        ice_query.iter_mut()
+    {
+        let z = transform.translation.z;
 
-        // Falling if there's nothing underneath
-        let mut player_is_falling = match tile_map.0.get(&under_tile_pos) {
-            Some(_) => false,
-            None => true,
-        };
+        // The player stepped under it while it was stopped on their tile
+        if occupancy.has_role_at(grid_pos.0, Role::Player) {
+            falling_ice.has_hit_player = true;
+        }
 
-        // But also if it's the top ladder tile
-        player_is_falling |= match tile_map.0.get(&player_tile_pos) {
-            None => true,
-            Some(_) => false,
-        } && match tile_map.0.get(&under_tile_pos) {
-            Some(TileType::Ladder) => true,
-            Some(_) => false,
-            None => false,
-        };
+        for _ in 0..settings.fall_speed {
+            let fallen_pos = grid_pos.0 - IVec2::Y;
 
-        if let Some(advantage) = &game_state.player_advantage {
-            if player_is_climbing {
-                *texture = match advantage {
-                    Advantage::Health => game_textures.player_health_climbing.clone(),
-                    Advantage::Speed => game_textures.player_speed_climbing.clone(),
-                    Advantage::Strength => game_textures.player_strength_climbing.clone(),
-                };
-            } else if player_is_falling {
-                *texture = match advantage {
-                    Advantage::Health => game_textures.player_health_falling.clone(),
-                    Advantage::Speed => game_textures.player_speed_falling.clone(),
-                    Advantage::Strength => game_textures.player_strength_falling.clone(),
-                };
-            } else {
-                *texture = match advantage {
-                    Advantage::Health => game_textures.player_health_standing.clone(),
-                    Advantage::Speed => game_textures.player_speed_standing.clone(),
-                    Advantage::Strength => game_textures.player_strength_standing.clone(),
-                };
+            // Hit the ground
+            if matches!(tile_map.0.get(&fallen_pos), Some(tile) if tile.is_solid()) {
+                let landed_pos = grid_pos.0;
+                let is_free = !tile_map.0.contains_key(&landed_pos)
+                    && occupancy.at(landed_pos).all(|(e, _)| e == entity);
+                if settings.leaves_debris && is_free {
+                    tile_map.0.insert(landed_pos, TileType::Debris);
+                    commands
+                        .spawn_bundle(SpriteBundle {
+                            sprite: Sprite {
+                                color: Color::rgba(0.8, 0.9, 1.0, 0.8),
+                                flip_y: true,
+                                ..Default::default()
+                            },
+                            texture: asset_server.load("FallingIce.png"),
+                            transform: Transform::from_translation(
+                                loaded_levels.grid_to_translation(landed_pos).extend(z),
+                            ),
+                            ..Default::default()
+                        })
+                        .insert(Debris {
+                            ticks_left: DEBRIS_TICKS,
+                        })
+                        .insert(GridPos(landed_pos));
+                }
+
+                shatter_ice(&mut commands, &loaded_levels, landed_pos, z);
+                audio.play_in_channel(
+                    game_sounds.ice_shatter_sfx.clone(),
+                    &AudioChannel::new(ICE_SHATTER_CHANNEL.to_owned()),
+                );
+                if !falling_ice.has_hit_player {
+                    run_stats.ice_dodged += 1;
+                }
+                remove_falling_ice(
+                    &mut commands,
+                    &mut occupancy,
+                    &loaded_levels,
+                    entity,
+                    settings,
+                    &mut grid_pos,
+                    &mut tween,
+                );
+                break;
+            }
+
+            // Hit an obstacle or an enemy, the player takes their damage with everything else
+            let hit_entities: Vec<Entity> = occupancy
+                .at(fallen_pos)
+                .filter(|(_, role)| matches!(role, Role::Obstacle | Role::Enemy))
+                .map(|(e, _)| e)
+                .collect();
+            if !hit_entities.is_empty() {
+                for hit_entity in hit_entities {
+                    if let Ok((mut health, status)) = target_query.get_mut(hit_entity) {
+                        health.0 -= damage.0;
+                        if let (Some(mut status), Some(effect)) = (status, inflicts.0) {
+                            status.add(effect);
+                        }
+                        if health.0 <= 0 {
+                            commands.entity(hit_entity).despawn();
+                            occupancy.remove(hit_entity);
+                        }
+                    }
+                }
+
+                shatter_ice(&mut commands, &loaded_levels, fallen_pos, z);
+                audio.play_in_channel(
+                    game_sounds.ice_shatter_sfx.clone(),
+                    &AudioChannel::new(ICE_SHATTER_CHANNEL.to_owned()),
+                );
+                if !falling_ice.has_hit_player {
+                    run_stats.ice_dodged += 1;
+                }
+                remove_falling_ice(
+                    &mut commands,
+                    &mut occupancy,
+                    &loaded_levels,
+                    entity,
+                    settings,
+                    &mut grid_pos,
+                    &mut tween,
+                );
+                break;
+            }
+
+            move_on_grid(&mut grid_pos, &mut tween, fallen_pos);
+
+            if !loaded_levels.contains(grid_pos.0) {
+                remove_falling_ice(
+                    &mut commands,
+                    &mut occupancy,
+                    &loaded_levels,
+                    entity,
+                    settings,
+                    &mut grid_pos,
+                    &mut tween,
+                );
+                break;
+            }
+
+            // Stop on the player so it gets hit, fast ice would go right through otherwise
+            if occupancy.has_role_at(grid_pos.0, Role::Player) {
+                falling_ice.has_hit_player = true;
+                break;
             }
         }
     }
 }
 
-pub fn run_if_world_should_update(game_state: Res<GameState>) -> ShouldRun {
-    if game_state.world_should_update {
-        ShouldRun::Yes
+// Ice that's done falling is gone, unless it respawns where it started
+fn remove_falling_ice(
+    commands: &mut Commands,
+    occupancy: &mut Occupancy,
+    loaded_levels: &LoadedLevels,
+    entity: Entity,
+    settings: &IceSettings,
+    grid_pos: &mut GridPos,
+    tween: &mut Tween,
+) {
+    if settings.respawns && loaded_levels.contains(settings.home) {
+        // Snap straight back up there, rather than flying back
+        grid_pos.0 = settings.home;
+        *tween = Tween::starting_at(*grid_pos);
+        tween.waypoints.push_back(settings.home);
+
+        commands
+            .entity(entity)
+            .insert(StaticIce::default())
+            .insert(Role::StaticIce)
+            .remove::<FallingIce>();
     } else {
-        ShouldRun::No
+        commands.entity(entity).despawn();
+        occupancy.remove(entity);
     }
 }
 
-fn entities_are_overlapping(t1: &Transform, t2: &Transform) -> bool {
-    let t1_on_grid = get_nearest_tile_on_grid(t1.translation.x, t1.translation.y);
-    let t2_on_grid = get_nearest_tile_on_grid(t2.translation.x, t2.translation.y);
-    t1_on_grid.0 == t2_on_grid.0 && t1_on_grid.1 == t2_on_grid.1
+// Sends shards of ice flying out of the given tile
+fn shatter_ice(commands: &mut Commands, loaded_levels: &LoadedLevels, grid_pos: IVec2, z: f32) {
+    let mut rng = rand::thread_rng();
+    let translation = loaded_levels.grid_to_translation(grid_pos);
+    let shard_size = loaded_levels.grid_size as f32 / 8.0;
+    for _ in 0..ICE_SHARD_COUNT {
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb_u8(214, 240, 255),
+                    custom_size: Some(Vec2::splat(shard_size)),
+                    ..Default::default()
+                },
+                // In front of everything on the level
+                transform: Transform::from_translation(translation.extend(z + 1.0)),
+                ..Default::default()
+            })
+            .insert(IceShard {
+                velocity: Vec2::new(rng.gen_range(-150.0..150.0), rng.gen_range(100.0..300.0)),
+                elapsed: 0.0,
+                grid_pos,
+            });
+    }
 }
 
-pub fn apply_damage_to_player(
+pub fn animate_ice_shards(
     mut commands: Commands,
-    mut player_query: Query<(&mut Health, &Damage, &mut Transform), With<Player>>,
-    mut obstacle_query: Query<
-        (Entity, Option<&Blocking>, &mut Health, &Damage, &Transform),
-        Without<Player>,
-    >,
-    game_state: Res<GameState>,
-    game_sounds: Res<GameSounds>,
-    audio: Res<Audio>,
+    time: Res<Time>,
+    mut shard_query: Query<(Entity, &mut IceShard, &mut Transform, &mut Sprite)>,
 ) {
-    if let Ok((mut player_health, player_damage, mut player_transform)) =
-        player_query.get_single_mut()
-    {
-        for (
-            obstacle_entity,
-            obstacle_blocking,
-            mut obstacle_health,
-            obstacle_damage,
-            obstacle_transform,
-        ) in obstacle_query.iter_mut()
-        {
-            if entities_are_overlapping(&player_transform, obstacle_transform) {
-                let mut obstacle_just_died = false;
-                if player_damage.0 > 0 && obstacle_health.0 > 0 {
-                    obstacle_health.0 -= player_damage.0;
+    for (entity, mut shard, mut transform, mut sprite) in shard_query.iter_mut() {
+        shard.elapsed += time.delta_seconds();
+        if shard.elapsed >= ICE_SHARD_LIFETIME {
+            commands.entity(entity).despawn();
+            continue;
+        }
 
-                    audio.play(game_sounds.player_attack_sfx.clone());
+        shard.velocity.y -= ICE_SHARD_GRAVITY * time.delta_seconds();
+        transform.translation += (shard.velocity * time.delta_seconds()).extend(0.0);
+        sprite.color.set_a(1.0 - shard.elapsed / ICE_SHARD_LIFETIME);
+    }
+}
 
-                    if obstacle_health.0 <= 0 {
-                        commands.entity(obstacle_entity).despawn();
-                        obstacle_just_died = true;
-                    }
-                }
+pub fn melt_debris(
+    mut commands: Commands,
+    mut tile_map: ResMut<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    mut game_state: ResMut<GameState>,
+    mut debris_query: Query<(Entity, &mut Debris, &GridPos)>,
+    mut player_query: Query<
+        (&mut GridPos, &mut Tween, &mut Health),
+        (With<Player>, Without<Debris>),
+    >,
+) {
+    if let Ok((mut player_pos, mut player_tween, mut player_health)) = player_query.get_single_mut()
+    {
+        for (entity, mut debris, debris_pos) in debris_query.iter_mut() {
+            if !loaded_levels.contains(debris_pos.0) {
+                // Its level is gone, along with its tiles
+                commands.entity(entity).despawn();
+                continue;
+            }
 
-                if !obstacle_just_died && obstacle_blocking.unwrap_or(&Blocking(false)).0 {
-                    player_transform.translation = game_state.player_previous_pos;
-                }
+            if debris.ticks_left > 1 {
+                debris.ticks_left -= 1;
+                continue;
+            }
 
-                if obstacle_damage.0 > 0 && player_health.0 > 0 {
-                    player_health.0 -= obstacle_damage.0;
+            // Only the debris itself melts, not anything that's been put there since
+            if let Some(TileType::Debris) = tile_map.0.get(&debris_pos.0) {
+                tile_map.0.remove(&debris_pos.0);
+            }
+            commands.entity(entity).despawn();
 
-                    let mut rng = rand::thread_rng();
-                    audio.play(
-                        game_sounds.player_hit_sfxs
-                            [rng.gen_range(0..game_sounds.player_hit_sfxs.len())]
-                        .clone(),
-                    );
-                }
+            if player_pos.0 == debris_pos.0 + IVec2::Y {
+                let current_pos = player_pos.0;
+                apply_gravity(
+                    &tile_map,
+                    &loaded_levels,
+                    &mut game_state,
+                    current_pos,
+                    &mut player_pos,
+                    &mut player_tween,
+                    &mut player_health,
+                );
             }
         }
     }
 }
 
-pub fn spawn_falling_ice_over_player(
+// Where an enemy goes on its next turn. Enemies fall like the player does, climb
+// when their next waypoint is above or below them, and walk towards it otherwise.
+// Going where the player is means attacking them instead.
+fn plan_enemy_step(
+    tile_map: &TileMap,
+    loaded_levels: &LoadedLevels,
+    occupancy: &Occupancy,
+    patrol: &Patrol,
+    enemy_pos: IVec2,
+) -> IVec2 {
+    if is_falling_at(tile_map, enemy_pos) {
+        return enemy_pos - IVec2::Y;
+    }
+
+    let target = match patrol.target_from(enemy_pos) {
+        Some((_, target)) => target,
+        None => return enemy_pos,
+    };
+
+    let towards_target = (target - enemy_pos).signum();
+    let mut directions = [
+        IVec2::new(towards_target.x, 0),
+        IVec2::new(0, towards_target.y),
+    ];
+    if let Some(TileType::Ladder) = tile_map.0.get(&enemy_pos) {
+        directions.reverse();
+    }
+
+    directions
+        .iter()
+        .map(|direction| enemy_pos + *direction)
+        .find(|new_pos| {
+            can_step(tile_map, loaded_levels, enemy_pos, *new_pos, false)
+                && !occupancy.has_role_at(*new_pos, Role::Obstacle)
+                && !occupancy.has_role_at(*new_pos, Role::Enemy)
+                && !occupancy.has_role_at(*new_pos, Role::PushBlock)
+        })
+        .unwrap_or(enemy_pos)
+}
+
+pub fn move_enemies(
     mut commands: Commands,
-    tile_map: ResMut<TileMap>,
-    player_query: Query<&Transform, With<Player>>,
-    ice_query: Query<(Entity, &Transform), With<StaticIce>>,
+    tile_map: Res<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    mut occupancy: ResMut<Occupancy>,
+    mut enemy_query: Query<
+        (
+            Entity,
+            &mut Patrol,
+            Option<&StatusEffects>,
+            &Damage,
+            &mut GridPos,
+            &mut Tween,
+        ),
+        With<Enemy>,
+    >,
+    mut player_query: Query<&mut Health, (With<Player>, Without<Enemy>)>,
     game_sounds: Res<GameSounds>,
     audio: Res<Audio>,
 ) {
-    if let Ok(transform) = player_query.get_single() {
-        let (x, y) = get_nearest_tile_on_grid(transform.translation.x, transform.translation.y);
-
-        for j in y..WORLD_SIZE {
-            let tile_to_inspect = (x, j);
+    for (entity, mut patrol, status, damage, mut grid_pos, mut tween) in enemy_query.iter_mut() {
+        // Frozen and stunned enemies stay where they are until it wears off
+        if status.map_or(false, |status| {
+            status.has(StatusKind::Frozen) || status.has(StatusKind::Stunned)
+        }) {
+            continue;
+        }
 
-            let mut found_ice = false;
+        let new_pos = plan_enemy_step(&tile_map, &loaded_levels, &occupancy, &patrol, grid_pos.0);
 
-            // Find matching ice
-            for (entity, ice_transform) in ice_query.iter() {
-                let ice_tile = get_nearest_tile_on_grid(
-                    ice_transform.translation.x,
-                    ice_transform.translation.y,
-                );
-                if ice_tile == tile_to_inspect {
-                    commands
-                        .entity(entity)
-                        .insert(FallingIce::default())
-                        .remove::<StaticIce>();
+        // The player is hit where they stand, and the enemy stays put
+        if occupancy.has_role_at(new_pos, Role::Player) {
+            if let Ok(mut player_health) = player_query.get_single_mut() {
+                if damage.0 > 0 && player_health.0 > 0 {
+                    player_health.0 -= damage.0;
 
-                    audio.play(game_sounds.falling_ice_sfx.clone());
-                    found_ice = true;
+                    let mut rng = rand::thread_rng();
+                    audio.play(
+                        game_sounds.player_hit_sfxs
+                            [rng.gen_range(0..game_sounds.player_hit_sfxs.len())]
+                        .clone(),
+                    );
                 }
             }
+            continue;
+        }
 
-            if found_ice {
-                break;
-            } else {
-                match tile_map.0.get(&tile_to_inspect) {
-                    Some(TileType::Wall) => {
-                        break;
-                    }
-                    Some(TileType::Ladder) => {} // go through
-                    None => {}                   // keep going
-                }
+        if let Some((target_index, _)) = patrol.target_from(grid_pos.0) {
+            patrol.next = target_index;
+            if new_pos == grid_pos.0 {
+                // Stuck, so give up on this waypoint and head for the one after it
+                patrol.next += 1;
+                continue;
             }
         }
+
+        move_on_grid(&mut grid_pos, &mut tween, new_pos);
+        occupancy.insert(entity, new_pos, Role::Enemy);
+
+        if !loaded_levels.contains(grid_pos.0) {
+            commands.entity(entity).despawn();
+            occupancy.remove(entity);
+        }
     }
 }
 
-pub fn move_falling_ice(
+pub fn spawn_enemy_telegraphs(
     mut commands: Commands,
-    mut ice_query: Query<(Entity, &mut Transform), With<FallingIce>>,
+    loaded_levels: Res<LoadedLevels>,
+    enemy_query: Query<(Entity, &GridPos, &Transform), Added<Enemy>>,
 ) {
-    for (entity, mut transform) in ice_query.iter_mut() {
-        let t = transform.translation.clone();
-        transform.translation = Vec3::new(t.x, t.y - TILE_SIZE as f32, t.z);
+    for (enemy_entity, enemy_pos, enemy_transform) in enemy_query.iter() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgba(0.9, 0.2, 0.2, 0.35),
+                    custom_size: Some(Vec2::splat(loaded_levels.grid_size as f32)),
+                    ..Default::default()
+                },
+                // Right under the enemy
+                transform: Transform::from_xyz(0.0, 0.0, enemy_transform.translation.z - 0.5),
+                visibility: Visibility { is_visible: false },
+                ..Default::default()
+            })
+            .insert(EnemyTelegraph(enemy_entity))
+            .insert(*enemy_pos);
+    }
+}
 
-        let (x, y) = get_nearest_tile_on_grid(transform.translation.x, transform.translation.y);
-        if x < 0 || y < 0 {
-            commands.entity(entity).despawn();
+pub fn telegraph_enemy_moves(
+    mut commands: Commands,
+    tile_map: Res<TileMap>,
+    loaded_levels: Res<LoadedLevels>,
+    occupancy: Res<Occupancy>,
+    enemy_query: Query<(&Patrol, &GridPos), With<Enemy>>,
+    mut telegraph_query: Query<
+        (Entity, &EnemyTelegraph, &mut GridPos, &mut Visibility),
+        Without<Enemy>,
+    >,
+) {
+    for (telegraph_entity, telegraph, mut grid_pos, mut visibility) in telegraph_query.iter_mut() {
+        if let Ok((patrol, enemy_pos)) = enemy_query.get(telegraph.0) {
+            let new_pos =
+                plan_enemy_step(&tile_map, &loaded_levels, &occupancy, patrol, enemy_pos.0);
+
+            visibility.is_visible = new_pos != enemy_pos.0;
+            if grid_pos.0 != new_pos {
+                grid_pos.0 = new_pos;
+            }
+        } else {
+            // Its enemy is gone
+            commands.entity(telegraph_entity).despawn();
         }
     }
 }
 
 const ASPECT_RATIO: f32 = 16.0 / 9.0;
+// How quickly the camera catches up when panning to another level, higher is faster
+const CAMERA_PAN_SPEED: f32 = 6.0;
 
 pub fn fit_camera_inside_current_level(
     mut camera_query: Query<
@@ -800,7 +3701,7 @@ pub fn fit_camera_inside_current_level(
             &mut bevy::render::camera::OrthographicProjection,
             &mut Transform,
         ),
-        Without<Player>,
+        (With<GameCamera>, Without<Player>),
     >,
     player_query: Query<&Transform, With<Player>>,
     level_query: Query<
@@ -810,7 +3711,22 @@ pub fn fit_camera_inside_current_level(
     level_selection: Res<LevelSelection>,
     ldtk_levels: Res<Assets<LdtkLevel>>,
     game_state: Res<GameState>,
+    world_layout: Res<WorldLayout>,
+    mut screen_shake: ResMut<ScreenShake>,
+    time: Res<Time>,
 ) {
+    // Shaking is laid on top of where the camera would be
+    screen_shake.time_left = (screen_shake.time_left - time.delta_seconds()).max(0.0);
+    let previous_shake_offset = screen_shake.offset;
+    screen_shake.offset = if screen_shake.time_left > 0.0 {
+        let mut rng = rand::thread_rng();
+        let strength = screen_shake.amplitude * screen_shake.time_left / SCREEN_SHAKE_DURATION;
+        Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)) * strength
+    } else {
+        screen_shake.amplitude = 0.0;
+        Vec2::ZERO
+    };
+
     if let Ok(Transform {
         translation: player_translation,
         ..
@@ -825,6 +3741,7 @@ pub fn fit_camera_inside_current_level(
                 let level = &ldtk_level.level;
                 if level_selection.is_match(&game_state.level_index, &level) {
                     let level_ratio = level.px_wid as f32 / ldtk_level.level.px_hei as f32;
+                    let mut camera_translation = Vec2::ZERO;
 
                     orthographic_projection.scaling_mode = bevy::render::camera::ScalingMode::None;
                     orthographic_projection.bottom = 0.0;
@@ -833,24 +3750,33 @@ pub fn fit_camera_inside_current_level(
                         // level is wider than the screen
                         orthographic_projection.top = (level.px_hei as f32 / 9.0).round() * 9.0;
                         orthographic_projection.right = orthographic_projection.top * ASPECT_RATIO;
-                        camera_transform.translation.x = (player_translation.x
+                        camera_translation.x = (player_translation.x
                             - level_transform.translation.x
                             - orthographic_projection.right / 2.0)
                             .clamp(0.0, level.px_wid as f32 - orthographic_projection.right);
-                        camera_transform.translation.y = 0.0;
                     } else {
                         // level is taller than the screen
                         orthographic_projection.right = (level.px_wid as f32 / 16.0).round() * 16.0;
                         orthographic_projection.top = orthographic_projection.right / ASPECT_RATIO;
-                        camera_transform.translation.y = (player_translation.y
+                        camera_translation.y = (player_translation.y
                             - level_transform.translation.y
                             - orthographic_projection.top / 2.0)
                             .clamp(0.0, level.px_hei as f32 - orthographic_projection.top);
-                        camera_transform.translation.x = 0.0;
                     }
 
-                    camera_transform.translation.x += level_transform.translation.x;
-                    camera_transform.translation.y += level_transform.translation.y;
+                    camera_translation += level_transform.translation.truncate();
+
+                    if *world_layout == WorldLayout::Connected {
+                        // Pan across to the next level instead of cutting to it
+                        let t = 1.0 - (-CAMERA_PAN_SPEED * time.delta_seconds()).exp();
+                        camera_translation = (camera_transform.translation.truncate()
+                            - previous_shake_offset)
+                            .lerp(camera_translation, t);
+                    }
+
+                    camera_translation += screen_shake.offset;
+                    camera_transform.translation.x = camera_translation.x;
+                    camera_transform.translation.y = camera_translation.y;
                 }
             }
         }